@@ -1,5 +1,4 @@
 use std::fs::File;
-use std::io;
 use std::io::prelude::*;
 use std::path::Path;
 
@@ -7,7 +6,7 @@ pub mod vm;
 
 pub fn run_file(machine: &mut vm::machine::Machine, file: &String) -> Result<(), std::io::Error> {
     let path = Path::new(file);
-    let mut file = File::open(&path)?;
+    let mut file = File::open(path)?;
 
     let mut source = String::new();
     file.read_to_string(&mut source)?;
@@ -20,68 +19,45 @@ pub fn run_prompt(machine: &mut vm::machine::Machine) -> Result<(), std::io::Err
 
     loop {
         let mut line = String::new();
-        io::stdin().read_line(&mut line)?;
-
-        run(machine, &line);
-    }
-}
-
-fn run(machine: &mut vm::machine::Machine, line: &String) {
-    let input = tokenize_input(line);
-
-    let mut had_error: bool = false;
-    match machine.execute(&input) {
-        Ok(_) => (),
-        Err(vm::ErrorType::WordNotFound) => {
-            had_error = true;
-        },
-        Err(vm::ErrorType::StackUnderflow) => {
-            println!("stack underflow");
-            had_error = true;
-        },
-        Err(vm::ErrorType::CompilationError) => {
-            println!("compilation error");
-            had_error = true;
-        },
-        Err(vm::ErrorType::OutsideCompileMode) => {
-            println!("compile operator used outside compile mode");
-            had_error = true;
-        },
-        Err(vm::ErrorType::InvalidOffset) => {
-            println!("invalid offset");
-            had_error = true;
-        },
-        Err(vm::ErrorType::BranchOutOfBounds) => {
-            println!("branch out of bounds");
-            had_error = true;
-        },
-        Err(vm::ErrorType::UnbalancedControl) => {
-            println!("unbalanced control structure");
-            had_error = true;
+        if machine.streams.input.read_line(&mut line)? == 0 {
+            return Ok(());
         }
-    }
 
-    if !had_error {
-        println!("ok");
+        run(machine, &line);
     }
 }
 
-fn tokenize_input(line: &String) -> Vec<vm::Value> {
-    let words = line.split_whitespace();
-    let mut input: Vec<vm::Value> = Vec::new();
-    for word in words {
-        let token = match word.parse::<i32>() {
-            Ok(num) => {
-                vm::Value::Number(num)
-            },
-            Err(_) => {
-                vm::Value::Word(word.to_string())
-            }
-        };
-
-        input.push(token);
+fn run(machine: &mut vm::machine::Machine, line: &str) {
+    let message = match machine.evaluate(line) {
+        Ok(_) => "ok",
+        // The machine has already reported the missing word.
+        Err(vm::ErrorType::WordNotFound) => "",
+        Err(vm::ErrorType::StackOverflow) => "stack overflow",
+        Err(vm::ErrorType::StackUnderflow) => "stack underflow",
+        Err(vm::ErrorType::CompilationError) => "compilation error",
+        Err(vm::ErrorType::OutsideCompileMode) => "compile operator used outside compile mode",
+        Err(vm::ErrorType::ReturnStackOverflow) => "return stack overflow",
+        Err(vm::ErrorType::DivisionByZero) => "division by zero",
+        Err(vm::ErrorType::DataSpaceOverflow) => "data space overflow",
+        Err(vm::ErrorType::DefinitionTooLong) => "definition too long",
+        Err(vm::ErrorType::DictionaryOverflow) => "dictionary overflow",
+        #[cfg(feature = "float")]
+        Err(vm::ErrorType::FloatStackOverflow) => "floating-point stack overflow",
+        #[cfg(feature = "float")]
+        Err(vm::ErrorType::FloatStackUnderflow) => "floating-point stack underflow",
+        Err(vm::ErrorType::InputTooLong) => "input line too long",
+        Err(vm::ErrorType::InvalidAddress) => "invalid address",
+        Err(vm::ErrorType::InvalidOffset) => "invalid offset",
+        Err(vm::ErrorType::IoError) => "i/o error",
+        Err(vm::ErrorType::BranchOutOfBounds) => "branch out of bounds",
+        Err(vm::ErrorType::UnbalancedControl) => "unbalanced control structure",
+        Err(vm::ErrorType::UnbalancedReturnStack) => "unbalanced return stack",
+        Err(vm::ErrorType::PicturedOutputOverflow) => "pictured numeric output overflow",
+        Err(vm::ErrorType::UninitializedDeferred) => "uninitialized deferred word",
+    };
+
+    if !message.is_empty() {
+        let _ = machine.print(&format!("{}\n", message));
     }
-
-    input
+    let _ = machine.flush();
 }
-
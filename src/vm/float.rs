@@ -0,0 +1,293 @@
+use crate::vm::instructions;
+use crate::vm::machine::Machine;
+use crate::vm::Cell;
+use crate::vm::ErrorType;
+use crate::vm::FLOAT_SIZE;
+
+// Parse a float literal such as `1.5e0`, `-2e3` or `1e`. The exponent marker
+// is what tells a float apart from an integer.
+pub fn parse_float(word: &str) -> Option<f64> {
+    let at = word.find(['e', 'E'])?;
+    let (mantissa, exponent) = (&word[..at], &word[at + 1..]);
+
+    let digits = mantissa.strip_prefix(['-', '+']).unwrap_or(mantissa);
+    if !digits.chars().any(|c| c.is_ascii_digit())
+        || !digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        || digits.matches('.').count() > 1
+    {
+        return None;
+    }
+
+    let power = exponent.strip_prefix(['-', '+']).unwrap_or(exponent);
+    if !power.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let exponent = if power.is_empty() { "0" } else { exponent };
+    format!("{}e{}", mantissa, exponent).parse().ok()
+}
+
+pub fn f_plus(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    let b = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(b + a)?;
+    Ok(())
+}
+
+pub fn f_minus(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    let b = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(b - a)?;
+    Ok(())
+}
+
+pub fn f_star(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    let b = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(b * a)?;
+    Ok(())
+}
+
+pub fn f_slash(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    let b = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(b / a)?;
+    Ok(())
+}
+
+pub fn f_dot(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.print(&format!("{} ", a))
+}
+
+pub fn f_dup(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(a)?;
+    machine.fpush(a)?;
+    Ok(())
+}
+
+pub fn f_drop(machine: &mut Machine) -> Result<(), ErrorType> {
+    let _a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    Ok(())
+}
+
+pub fn f_swap(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    let b = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(a)?;
+    machine.fpush(b)?;
+    Ok(())
+}
+
+pub fn f_over(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    let b = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(b)?;
+    machine.fpush(a)?;
+    machine.fpush(b)?;
+    Ok(())
+}
+
+pub fn f_fetch(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let a = machine.fetch_float(addr)?;
+    machine.fpush(a)?;
+    Ok(())
+}
+
+pub fn f_store(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.store_float(addr, a)
+}
+
+pub fn f_variable(machine: &mut Machine) -> Result<(), ErrorType> {
+    instructions::create_word(machine, None)?;
+    machine.allot(FLOAT_SIZE as Cell)
+}
+
+pub fn f_constant(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+
+    let body = instructions::create_word(machine, Some("f@"))?;
+    machine.allot(FLOAT_SIZE as Cell)?;
+    machine.store_float(body as Cell, a)
+}
+
+pub fn f_less_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    let b = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.push(if b < a { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn f_greater_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    let b = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.push(if b > a { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn f_eq(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    let b = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.push(if b == a { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn f_zero_less_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.push(if a < 0.0 { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn f_zero_eq(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.push(if a == 0.0 { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn f_sqrt(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(a.sqrt())?;
+    Ok(())
+}
+
+pub fn f_sin(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(a.sin())?;
+    Ok(())
+}
+
+pub fn f_cos(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(a.cos())?;
+    Ok(())
+}
+
+pub fn f_exp(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(a.exp())?;
+    Ok(())
+}
+
+pub fn f_ln(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.fpush(a.ln())?;
+    Ok(())
+}
+
+pub fn s_to_f(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.fpush(a as f64)?;
+    Ok(())
+}
+
+pub fn f_to_s(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.fpop() {
+        Some(n) => n,
+        None => return Err(ErrorType::FloatStackUnderflow)
+    };
+    machine.push(a as Cell)?;
+    Ok(())
+}
@@ -1,6 +1,14 @@
+use crate::vm::machine::Entry;
+use crate::vm::machine::Function;
 use crate::vm::machine::Machine;
 use crate::vm::ErrorType;
-use crate::vm::Value;
+use crate::vm::Cell;
+use crate::vm::DCell;
+use crate::vm::Instruction;
+use crate::vm::UCell;
+use crate::vm::UDCell;
+use crate::vm::CELL_SIZE;
+use crate::vm::HOLD_SIZE;
 
 pub fn add(machine: &mut Machine) -> Result<(), ErrorType> {
     let a = match machine.pop() {
@@ -11,7 +19,7 @@ pub fn add(machine: &mut Machine) -> Result<(), ErrorType> {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    machine.push(a + b);
+    machine.push(b.wrapping_add(a))?;
     Ok(())
 }
 
@@ -24,7 +32,7 @@ pub fn sub(machine: &mut Machine) -> Result<(), ErrorType> {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    machine.push(a - b);
+    machine.push(b.wrapping_sub(a))?;
     Ok(())
 }
 
@@ -37,7 +45,7 @@ pub fn mult(machine: &mut Machine) -> Result<(), ErrorType> {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    machine.push(a * b);
+    machine.push(b.wrapping_mul(a))?;
     Ok(())
 }
 
@@ -50,7 +58,8 @@ pub fn div(machine: &mut Machine) -> Result<(), ErrorType> {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    machine.push(a / b);
+    let (_, quot) = divide(machine, b as DCell, a as DCell)?;
+    machine.push(quot as Cell)?;
     Ok(())
 }
 
@@ -63,7 +72,213 @@ pub fn mod_(machine: &mut Machine) -> Result<(), ErrorType> {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    machine.push(a % b);
+    let (rem, _) = divide(machine, b as DCell, a as DCell)?;
+    machine.push(rem as Cell)?;
+    Ok(())
+}
+
+// Divide rounding toward zero, giving the remainder and quotient.
+fn symmetric_divide(n: DCell, d: DCell) -> Result<(DCell, DCell), ErrorType> {
+    if d == 0 {
+        return Err(ErrorType::DivisionByZero);
+    }
+    Ok((n.wrapping_rem(d), n.wrapping_div(d)))
+}
+
+// Divide rounding toward negative infinity, giving the remainder and quotient.
+fn floored_divide(n: DCell, d: DCell) -> Result<(DCell, DCell), ErrorType> {
+    let (rem, quot) = symmetric_divide(n, d)?;
+    if rem != 0 && (rem ^ d) < 0 {
+        Ok((rem + d, quot - 1))
+    } else {
+        Ok((rem, quot))
+    }
+}
+
+// Divide the way the machine is configured to round.
+fn divide(machine: &Machine, n: DCell, d: DCell) -> Result<(DCell, DCell), ErrorType> {
+    if machine.floored {
+        floored_divide(n, d)
+    } else {
+        symmetric_divide(n, d)
+    }
+}
+
+pub fn slash_mod(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let (rem, quot) = divide(machine, b as DCell, a as DCell)?;
+    machine.push(rem as Cell)?;
+    machine.push(quot as Cell)?;
+    Ok(())
+}
+
+// Multiply the second and third cells into a double product and divide it
+// by the top one.
+fn scale(machine: &mut Machine) -> Result<(DCell, DCell), ErrorType> {
+    let c = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    divide(machine, a as DCell * b as DCell, c as DCell)
+}
+
+pub fn star_slash(machine: &mut Machine) -> Result<(), ErrorType> {
+    let (_, quot) = scale(machine)?;
+    machine.push(quot as Cell)?;
+    Ok(())
+}
+
+pub fn star_slash_mod(machine: &mut Machine) -> Result<(), ErrorType> {
+    let (rem, quot) = scale(machine)?;
+    machine.push(rem as Cell)?;
+    machine.push(quot as Cell)?;
+    Ok(())
+}
+
+pub fn um_star(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let product = a as UCell as UDCell * b as UCell as UDCell;
+    push_double(machine, product as DCell)?;
+    Ok(())
+}
+
+pub fn um_slash_mod(machine: &mut Machine) -> Result<(), ErrorType> {
+    let d = match machine.pop() {
+        Some(n) => n as UCell as UDCell,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let ud = pop_double(machine)? as UDCell;
+    if d == 0 {
+        return Err(ErrorType::DivisionByZero);
+    }
+    machine.push((ud % d) as Cell)?;
+    machine.push((ud / d) as Cell)?;
+    Ok(())
+}
+
+pub fn fm_slash_mod(machine: &mut Machine) -> Result<(), ErrorType> {
+    let d = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let n = pop_double(machine)?;
+    let (rem, quot) = floored_divide(n, d as DCell)?;
+    machine.push(rem as Cell)?;
+    machine.push(quot as Cell)?;
+    Ok(())
+}
+
+pub fn sm_slash_rem(machine: &mut Machine) -> Result<(), ErrorType> {
+    let d = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let n = pop_double(machine)?;
+    let (rem, quot) = symmetric_divide(n, d as DCell)?;
+    machine.push(rem as Cell)?;
+    machine.push(quot as Cell)?;
+    Ok(())
+}
+
+pub fn negate(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a.wrapping_neg())?;
+    Ok(())
+}
+
+pub fn abs(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a.wrapping_abs())?;
+    Ok(())
+}
+
+pub fn min(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(b.min(a))?;
+    Ok(())
+}
+
+pub fn max(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(b.max(a))?;
+    Ok(())
+}
+
+pub fn one_plus(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a.wrapping_add(1))?;
+    Ok(())
+}
+
+pub fn one_minus(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a.wrapping_sub(1))?;
+    Ok(())
+}
+
+pub fn two_star(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a << 1)?;
+    Ok(())
+}
+
+pub fn two_slash(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a >> 1)?;
     Ok(())
 }
 
@@ -72,120 +287,1226 @@ pub fn dup(machine: &mut Machine) -> Result<(), ErrorType> {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    machine.push(a);
-    machine.push(a);
-    Ok(())
+    machine.push(a)?;
+    machine.push(a)?;
+    Ok(())
+}
+
+pub fn drop(machine: &mut Machine) -> Result<(), ErrorType> {
+    let _a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    Ok(())
+}
+
+pub fn swap(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a)?;
+    machine.push(b)?;
+    Ok(())
+}
+
+pub fn over(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(b)?;
+    machine.push(a)?;
+    machine.push(b)?;
+    Ok(())
+}
+
+pub fn rot(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let c = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(b)?;
+    machine.push(a)?;
+    machine.push(c)?;
+    Ok(())
+}
+
+pub fn nip(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let _b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a)?;
+    Ok(())
+}
+
+pub fn tuck(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a)?;
+    machine.push(b)?;
+    machine.push(a)?;
+    Ok(())
+}
+
+pub fn minus_rot(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let c = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a)?;
+    machine.push(c)?;
+    machine.push(b)?;
+    Ok(())
+}
+
+pub fn question_dup(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a)?;
+    if a != 0 {
+        machine.push(a)?;
+    }
+    Ok(())
+}
+
+// Check that there are `u` items below the index on top of the stack.
+fn require_index(machine: &Machine) -> Result<usize, ErrorType> {
+    let u = machine.stack[machine.stack.len() - 1];
+    if u < 0 {
+        return Err(ErrorType::StackUnderflow);
+    }
+    machine.require(u as usize + 2)?;
+    Ok(u as usize)
+}
+
+pub fn pick(machine: &mut Machine) -> Result<(), ErrorType> {
+    let u = require_index(machine)?;
+    machine.pop();
+    let a = machine.stack[machine.stack.len() - 1 - u];
+    machine.push(a)?;
+    Ok(())
+}
+
+pub fn roll(machine: &mut Machine) -> Result<(), ErrorType> {
+    let u = require_index(machine)?;
+    machine.pop();
+    let a = machine.stack.remove(machine.stack.len() - 1 - u);
+    machine.push(a)?;
+    Ok(())
+}
+
+pub fn depth(machine: &mut Machine) -> Result<(), ErrorType> {
+    let n = machine.stack.len() as Cell;
+    machine.push(n)?;
+    Ok(())
+}
+
+pub fn two_dup(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    push_double(machine, a)?;
+    push_double(machine, a)?;
+    Ok(())
+}
+
+pub fn two_drop(machine: &mut Machine) -> Result<(), ErrorType> {
+    pop_double(machine)?;
+    Ok(())
+}
+
+pub fn two_swap(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    let b = pop_double(machine)?;
+    push_double(machine, a)?;
+    push_double(machine, b)?;
+    Ok(())
+}
+
+pub fn two_over(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    let b = pop_double(machine)?;
+    push_double(machine, b)?;
+    push_double(machine, a)?;
+    push_double(machine, b)?;
+    Ok(())
+}
+
+pub fn two_rot(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    let b = pop_double(machine)?;
+    let c = pop_double(machine)?;
+    push_double(machine, b)?;
+    push_double(machine, a)?;
+    push_double(machine, c)?;
+    Ok(())
+}
+
+pub fn dot(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let text = format_number(a as DCell, machine.radix());
+    machine.print(&format!("{} ", text))
+}
+
+// Digits for every radix up to 36.
+const DIGITS: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+// Format a signed number in the given radix.
+pub fn format_number(n: DCell, radix: u32) -> String {
+    let mut magnitude = n.unsigned_abs();
+    let mut digits: Vec<char> = Vec::new();
+    loop {
+        digits.push(DIGITS[(magnitude % radix as UDCell) as usize] as char);
+        magnitude /= radix as UDCell;
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        digits.push('-');
+    }
+
+    digits.iter().rev().collect()
+}
+
+pub fn sdot(machine: &mut Machine) -> Result<(), ErrorType> {
+    let radix = machine.radix();
+    let mut text = format!("<{}> ", machine.stack.len());
+    for n in &machine.stack {
+        text.push_str(&format!("{} ", format_number(*n as DCell, radix)));
+    }
+    machine.print(&text)
+}
+
+pub fn u_dot(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let text = format_number(a as UCell as DCell, machine.radix());
+    machine.print(&format!("{} ", text))
+}
+
+// Print text right-aligned in a field of the given width.
+fn print_right(machine: &mut Machine, text: &str, width: Cell) -> Result<(), ErrorType> {
+    let padding = (width.max(0) as usize).saturating_sub(text.chars().count());
+    write_spaces(machine, padding)?;
+    machine.print(text)
+}
+
+pub fn dot_r(machine: &mut Machine) -> Result<(), ErrorType> {
+    let width = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let text = format_number(a as DCell, machine.radix());
+    print_right(machine, &text, width)
+}
+
+pub fn u_dot_r(machine: &mut Machine) -> Result<(), ErrorType> {
+    let width = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let text = format_number(a as UCell as DCell, machine.radix());
+    print_right(machine, &text, width)
+}
+
+// A double-cell number is two cells with the high cell on top.
+pub fn join_double(low: Cell, high: Cell) -> DCell {
+    ((high as DCell) << Cell::BITS) | (low as UCell as DCell)
+}
+
+pub fn push_double(machine: &mut Machine, d: DCell) -> Result<(), ErrorType> {
+    machine.push(d as Cell)?;
+    machine.push((d >> Cell::BITS) as Cell)
+}
+
+fn pop_double(machine: &mut Machine) -> Result<DCell, ErrorType> {
+    let high = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let low = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    Ok(join_double(low, high))
+}
+
+pub fn d_dot(machine: &mut Machine) -> Result<(), ErrorType> {
+    let d = pop_double(machine)?;
+    let text = format_number(d, machine.radix());
+    machine.print(&format!("{} ", text))
+}
+
+pub fn d_plus(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    let b = pop_double(machine)?;
+    push_double(machine, b.wrapping_add(a))?;
+    Ok(())
+}
+
+pub fn d_minus(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    let b = pop_double(machine)?;
+    push_double(machine, b.wrapping_sub(a))?;
+    Ok(())
+}
+
+pub fn d_negate(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    push_double(machine, a.wrapping_neg())?;
+    Ok(())
+}
+
+pub fn d_abs(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    push_double(machine, a.wrapping_abs())?;
+    Ok(())
+}
+
+pub fn d_less_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    let b = pop_double(machine)?;
+    machine.push(if b < a { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn d_eq(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    let b = pop_double(machine)?;
+    machine.push(if b == a { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn d_to_s(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = pop_double(machine)?;
+    machine.push(a as Cell)?;
+    Ok(())
+}
+
+pub fn s_to_d(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    push_double(machine, a as DCell)?;
+    Ok(())
+}
+
+pub fn m_star(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    push_double(machine, b as DCell * a as DCell)?;
+    Ok(())
+}
+
+pub fn m_plus(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = pop_double(machine)?;
+    push_double(machine, b.wrapping_add(a as DCell))?;
+    Ok(())
+}
+
+pub fn less_number_sign(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.hold = machine.hold_area + HOLD_SIZE as Cell;
+    Ok(())
+}
+
+// Prepend a character to the pictured output being built.
+fn hold_char(machine: &mut Machine, c: u8) -> Result<(), ErrorType> {
+    if machine.hold <= machine.hold_area {
+        return Err(ErrorType::PicturedOutputOverflow);
+    }
+    machine.hold -= 1;
+    machine.store_byte(machine.hold, c)
+}
+
+pub fn number_sign(machine: &mut Machine) -> Result<(), ErrorType> {
+    let ud = pop_double(machine)? as UDCell;
+    let radix = machine.radix() as UDCell;
+    hold_char(machine, DIGITS[(ud % radix) as usize])?;
+    push_double(machine, (ud / radix) as DCell)?;
+    Ok(())
+}
+
+pub fn number_sign_s(machine: &mut Machine) -> Result<(), ErrorType> {
+    loop {
+        number_sign(machine)?;
+        if machine.stack[machine.stack.len() - 2..] == [0, 0] {
+            return Ok(());
+        }
+    }
+}
+
+pub fn number_sign_greater(machine: &mut Machine) -> Result<(), ErrorType> {
+    pop_double(machine)?;
+    let end = machine.hold_area + HOLD_SIZE as Cell;
+    machine.push(machine.hold)?;
+    machine.push(end - machine.hold)?;
+    Ok(())
+}
+
+pub fn hold(machine: &mut Machine) -> Result<(), ErrorType> {
+    let c = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    hold_char(machine, c as u8)
+}
+
+pub fn holds(machine: &mut Machine) -> Result<(), ErrorType> {
+    let len = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let addr = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let text = machine.bytes(addr, len)?.to_vec();
+    for c in text.into_iter().rev() {
+        hold_char(machine, c)?;
+    }
+    Ok(())
+}
+
+pub fn sign(machine: &mut Machine) -> Result<(), ErrorType> {
+    let n = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    if n < 0 {
+        hold_char(machine, b'-')?;
+    }
+    Ok(())
+}
+
+pub fn dot_quote(machine: &mut Machine) -> Result<(), ErrorType> {
+    let text = parse_string(machine);
+    if !machine.compiling() {
+        return machine.print(&text);
+    }
+
+    compile_string(machine, text.as_bytes())?;
+    machine.compile_instruction(Instruction::Call(machine.builtins["type"]))?;
+    Ok(())
+}
+
+// Parse the input up to a closing quote.
+pub fn parse_string(machine: &mut Machine) -> String {
+    machine.parse(|c| c == b'"').0
+}
+
+// Parse the input up to an unescaped closing quote, translating escapes.
+fn parse_escaped_string(machine: &mut Machine) -> Vec<u8> {
+    let input = machine.parse_area().to_vec();
+    let mut text: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < input.len() && input[i] != b'"' {
+        if input[i] != b'\\' || i + 1 >= input.len() {
+            text.push(input[i]);
+            i += 1;
+            continue;
+        }
+
+        i += 1;
+        match input[i] {
+            b'a' => text.push(7),
+            b'b' => text.push(8),
+            b'e' => text.push(27),
+            b'f' => text.push(12),
+            b'l' | b'n' => text.push(b'\n'),
+            b'm' => text.extend_from_slice(b"\r\n"),
+            b'q' => text.push(b'"'),
+            b'r' => text.push(b'\r'),
+            b't' => text.push(b'\t'),
+            b'v' => text.push(11),
+            b'z' => text.push(0),
+            b'x' => {
+                let digits = input.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+                if let Some(n) = digits.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    text.push(n);
+                    i += 2;
+                }
+            },
+            c => text.push(c),
+        }
+        i += 1;
+    }
+
+    machine.skip_input(i + 1);
+    text
+}
+
+// Copy a string into data space, returning its address.
+fn store_string(machine: &mut Machine, text: &[u8]) -> Result<Cell, ErrorType> {
+    let addr = machine.here() as Cell;
+    machine.allot(text.len() as Cell)?;
+    for (i, c) in text.iter().enumerate() {
+        machine.store_byte(addr + i as Cell, *c)?;
+    }
+    Ok(addr)
+}
+
+// Store a string in data space and arrange for its address and length to
+// be pushed, either now or when the current definition runs.
+fn compile_string(machine: &mut Machine, text: &[u8]) -> Result<(), ErrorType> {
+    let addr = store_string(machine, text)?;
+    let len = text.len() as Cell;
+    if machine.compiling() {
+        machine.compile_instruction(Instruction::Literal(addr))?;
+        machine.compile_instruction(Instruction::Literal(len))?;
+    } else {
+        machine.push(addr)?;
+        machine.push(len)?;
+    }
+    Ok(())
+}
+
+pub fn s_quote(machine: &mut Machine) -> Result<(), ErrorType> {
+    let text = parse_string(machine);
+    compile_string(machine, text.as_bytes())
+}
+
+pub fn s_backslash_quote(machine: &mut Machine) -> Result<(), ErrorType> {
+    let text = parse_escaped_string(machine);
+    compile_string(machine, &text)
+}
+
+pub fn c_quote(machine: &mut Machine) -> Result<(), ErrorType> {
+    let text = parse_string(machine);
+    if text.len() > u8::MAX as usize {
+        return Err(ErrorType::InvalidAddress);
+    }
+
+    let mut counted = vec![text.len() as u8];
+    counted.extend_from_slice(text.as_bytes());
+    let addr = store_string(machine, &counted)?;
+    if machine.compiling() {
+        machine.compile_instruction(Instruction::Literal(addr))?;
+    } else {
+        machine.push(addr)?;
+    }
+    Ok(())
+}
+
+pub fn count(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    let len = machine.fetch_byte(addr)?;
+    machine.push(addr.wrapping_add(1))?;
+    machine.push(len as Cell)?;
+    Ok(())
+}
+
+pub fn eq(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    if a == b {
+        machine.push(-1)?;
+    } else {
+        machine.push(0)?;
+    }
+
+    Ok(())
+}
+
+pub fn greater_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    if b > a {
+        machine.push(-1)?;
+    } else {
+        machine.push(0)?;
+    }
+
+    Ok(())
+}
+
+pub fn less_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    if b < a {
+        machine.push(-1)?;
+    } else {
+        machine.push(0)?;
+    }
+
+    Ok(())
+}
+
+pub fn not_eq(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(if a != b { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn u_less_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(if (b as UCell) < (a as UCell) { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn u_greater_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(if (b as UCell) > (a as UCell) { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn zero_eq(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(if a == 0 { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn zero_less_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(if a < 0 { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn zero_greater_than(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(if a > 0 { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn within(machine: &mut Machine) -> Result<(), ErrorType> {
+    let hi = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let lo = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let n = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(if (n.wrapping_sub(lo) as UCell) < (hi.wrapping_sub(lo) as UCell) { -1 } else { 0 })?;
+    Ok(())
+}
+
+pub fn and(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(a & b)?;
+
+    Ok(())
+}
+
+pub fn or(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(a | b)?;
+
+    Ok(())
+}
+
+pub fn invert(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(!a)?;
+
+    Ok(())
+}
+
+pub fn xor(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push(a ^ b)?;
+    Ok(())
+}
+
+pub fn lshift(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push((b as UCell).checked_shl(a as u32).unwrap_or(0) as Cell)?;
+    Ok(())
+}
+
+pub fn rshift(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    machine.push((b as UCell).checked_shr(a as u32).unwrap_or(0) as Cell)?;
+    Ok(())
+}
+
+pub fn clearstack(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.stack.clear();
+    Ok(())
+}
+
+pub fn branch0(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    if a != 0 {
+        machine.pc += 1;
+        return Ok(());
+    }
+
+    jump(machine)
+}
+
+pub fn branch(machine: &mut Machine) -> Result<(), ErrorType> {
+    jump(machine)
+}
+
+// Read the inline literal the pc currently points at.
+fn inline_literal(machine: &Machine) -> Result<Cell, ErrorType> {
+    match machine.instruction(machine.pc) {
+        Some(Instruction::Literal(n)) => Ok(n),
+        Some(_) => Err(ErrorType::InvalidOffset),
+        None => Err(ErrorType::BranchOutOfBounds),
+    }
+}
+
+// Move the pc by the offset stored in the cell it currently points at.
+fn jump(machine: &mut Machine) -> Result<(), ErrorType> {
+    let n = inline_literal(machine)?;
+
+    let target = machine.pc as isize + n as isize;
+    if target < 0 || target > machine.code_len() as isize {
+        return Err(ErrorType::BranchOutOfBounds);
+    }
+
+    machine.pc = target as usize;
+    Ok(())
+}
+
+pub fn of(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    // On a match the selector is consumed, otherwise it is kept for the
+    // next of.
+    if a == b {
+        machine.pc += 1;
+        return Ok(());
+    }
+
+    machine.push(b)?;
+    jump(machine)
+}
+
+pub fn do_(machine: &mut Machine) -> Result<(), ErrorType> {
+    let index = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let limit = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.loop_stack.push((limit, index));
+    Ok(())
+}
+
+pub fn question_do(machine: &mut Machine) -> Result<(), ErrorType> {
+    let index = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let limit = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    // Skip the loop entirely when there is nothing to iterate over.
+    if index == limit {
+        return jump(machine);
+    }
+
+    machine.loop_stack.push((limit, index));
+    machine.pc += 1;
+    Ok(())
+}
+
+pub fn loop_(machine: &mut Machine) -> Result<(), ErrorType> {
+    step_loop(machine, 1)
+}
+
+pub fn plus_loop(machine: &mut Machine) -> Result<(), ErrorType> {
+    let step = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    step_loop(machine, step)
+}
+
+// The loop finishes when the index crosses the boundary between limit - 1
+// and limit, in either direction.
+fn step_loop(machine: &mut Machine, step: Cell) -> Result<(), ErrorType> {
+    let (limit, index) = match machine.loop_stack.pop() {
+        Some(frame) => frame,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    let before = index.wrapping_sub(limit);
+    let after = before.wrapping_add(step);
+    if (before ^ after) & (before ^ step) < 0 {
+        machine.pc += 1;
+        return Ok(());
+    }
+
+    machine.loop_stack.push((limit, index.wrapping_add(step)));
+    jump(machine)
+}
+
+pub fn leave(machine: &mut Machine) -> Result<(), ErrorType> {
+    if machine.loop_stack.pop().is_none() {
+        return Err(ErrorType::StackUnderflow);
+    }
+
+    jump(machine)
+}
+
+pub fn unloop(machine: &mut Machine) -> Result<(), ErrorType> {
+    if machine.loop_stack.pop().is_none() {
+        return Err(ErrorType::StackUnderflow);
+    }
+
+    Ok(())
+}
+
+pub fn exit(machine: &mut Machine) -> Result<(), ErrorType> {
+    if machine.code.is_none() {
+        return Err(ErrorType::OutsideCompileMode);
+    }
+
+    machine.exit();
+    Ok(())
+}
+
+pub fn i(machine: &mut Machine) -> Result<(), ErrorType> {
+    let (_, index) = match machine.loop_stack.last() {
+        Some(frame) => *frame,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(index)?;
+    Ok(())
+}
+
+pub fn j(machine: &mut Machine) -> Result<(), ErrorType> {
+    let depth = machine.loop_stack.len();
+    if depth < 2 {
+        return Err(ErrorType::StackUnderflow);
+    }
+
+    let (_, index) = machine.loop_stack[depth - 2];
+    machine.push(index)?;
+    Ok(())
+}
+
+pub fn to_r(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.rpush(a)
+}
+
+pub fn from_r(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.return_stack.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(a)?;
+    Ok(())
+}
+
+pub fn r_fetch(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.return_stack.last() {
+        Some(n) => *n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(a)?;
+    Ok(())
+}
+
+pub fn two_to_r(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.return_stack_room(2)?;
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.return_stack.push(b);
+    machine.rpush(a)
+}
+
+pub fn two_from_r(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.return_stack.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let b = match machine.return_stack.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(b)?;
+    machine.push(a)?;
+    Ok(())
+}
+
+pub fn two_r_fetch(machine: &mut Machine) -> Result<(), ErrorType> {
+    let depth = machine.return_stack.len();
+    if depth < 2 {
+        return Err(ErrorType::StackUnderflow);
+    }
+
+    let b = machine.return_stack[depth - 2];
+    let a = machine.return_stack[depth - 1];
+    machine.push(b)?;
+    machine.push(a)?;
+    Ok(())
+}
+
+pub fn here(machine: &mut Machine) -> Result<(), ErrorType> {
+    let here = machine.here();
+    machine.push(here as Cell)?;
+    Ok(())
+}
+
+pub fn allot(machine: &mut Machine) -> Result<(), ErrorType> {
+    let n = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.allot(n)
 }
 
-pub fn drop(machine: &mut Machine) -> Result<(), ErrorType> {
-    let _a = match machine.pop() {
+pub fn comma(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    Ok(())
+
+    let here = machine.here() as Cell;
+    machine.allot(CELL_SIZE as Cell)?;
+    machine.store(here, a)
 }
 
-pub fn swap(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn c_comma(machine: &mut Machine) -> Result<(), ErrorType> {
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    let b = match machine.pop() {
+
+    let here = machine.here() as Cell;
+    machine.allot(1)?;
+    machine.store_byte(here, a as u8)
+}
+
+fn align_up(addr: Cell) -> Cell {
+    let cell = CELL_SIZE as isize;
+    ((addr as isize + cell - 1) / cell * cell) as Cell
+}
+
+pub fn align(machine: &mut Machine) -> Result<(), ErrorType> {
+    let here = machine.here() as Cell;
+    machine.allot(align_up(here) - here)
+}
+
+pub fn aligned(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(align_up(addr))?;
+    Ok(())
+}
+
+pub fn fetch(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    machine.push(a);
-    machine.push(b);
+
+    let a = machine.fetch(addr)?;
+    machine.push(a)?;
     Ok(())
 }
 
-pub fn over(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn store(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    let b = match machine.pop() {
+
+    machine.store(addr, a)
+}
+
+pub fn c_fetch(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    machine.push(b);
-    machine.push(a);
-    machine.push(b);
+
+    let a = machine.fetch_byte(addr)?;
+    machine.push(a as Cell)?;
     Ok(())
 }
 
-pub fn rot(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn c_store(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    let b = match machine.pop() {
+
+    machine.store_byte(addr, a as u8)
+}
+
+pub fn plus_store(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    let c = match machine.pop() {
+    let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    machine.push(b);
-    machine.push(a);
-    machine.push(c);
-    Ok(())
+
+    let b = machine.fetch(addr)?;
+    machine.store(addr, a.wrapping_add(b))
 }
 
-pub fn dot(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn cells(machine: &mut Machine) -> Result<(), ErrorType> {
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    println!("{}", a);
+
+    machine.push(a.wrapping_mul(CELL_SIZE as Cell))?;
     Ok(())
 }
 
-pub fn sdot(machine: &mut Machine) -> Result<(), ErrorType> {
-    println!("{:?}", machine.stack);
+pub fn cell_plus(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(a.wrapping_add(CELL_SIZE as Cell))?;
     Ok(())
 }
 
-pub fn dot_quote(machine: &mut Machine) -> Result<(), ErrorType> {
-    while machine.pc < machine.data.len() {
-        match &machine.data[machine.pc] {
-            Value::Word(w) => {
-                if w.ends_with("\"") {
-                    let w_quote = w.replace("\"", "");
-                    println!("{}", w_quote);
-                    machine.pc += 1;
-                    break;
-                }
-                print!("{} ", w)
-            },
-            Value::Number(n) => print!("{} ", n.to_string()),
-        }
-        machine.pc += 1;
-    }
+pub fn chars(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    machine.push(a)?;
     Ok(())
 }
 
-pub fn eq(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn char_plus(machine: &mut Machine) -> Result<(), ErrorType> {
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    let b = match machine.pop() {
+
+    machine.push(a.wrapping_add(1))?;
+    Ok(())
+}
+
+pub fn two_fetch(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    if a == b {
-        machine.push(-1);
-    } else {
-        machine.push(0);
-    }
-
+    let a = machine.fetch(addr)?;
+    let b = machine.fetch(addr.wrapping_add(CELL_SIZE as Cell))?;
+    machine.push(b)?;
+    machine.push(a)?;
     Ok(())
 }
 
-pub fn greater_than(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn two_store(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
@@ -195,16 +1516,48 @@ pub fn greater_than(machine: &mut Machine) -> Result<(), ErrorType> {
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    if b > a {
-        machine.push(-1);
-    } else {
-        machine.push(0);
-    }
+    machine.store(addr, a)?;
+    machine.store(addr.wrapping_add(CELL_SIZE as Cell), b)
+}
+
+// Start a new created word whose data field begins at the aligned here.
+pub fn create_word(machine: &mut Machine, does: Option<&str>) -> Result<usize, ErrorType> {
+    let name = machine.parse_name()?;
+    align(machine)?;
+
+    let body = machine.here();
+    let does = does.map(|word| machine.builtins[word]);
+    machine.define_checked(&name, Function::Created { body, does })?;
+    Ok(body)
+}
 
+pub fn create(machine: &mut Machine) -> Result<(), ErrorType> {
+    create_word(machine, None)?;
     Ok(())
 }
 
-pub fn less_than(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn variable(machine: &mut Machine) -> Result<(), ErrorType> {
+    create_word(machine, None)?;
+    machine.allot(CELL_SIZE as Cell)
+}
+
+pub fn two_variable(machine: &mut Machine) -> Result<(), ErrorType> {
+    create_word(machine, None)?;
+    machine.allot(2 * CELL_SIZE as Cell)
+}
+
+pub fn constant(machine: &mut Machine) -> Result<(), ErrorType> {
+    let a = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    let body = create_word(machine, Some("@"))?;
+    machine.allot(CELL_SIZE as Cell)?;
+    machine.store(body as Cell, a)
+}
+
+pub fn two_constant(machine: &mut Machine) -> Result<(), ErrorType> {
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
@@ -214,190 +1567,412 @@ pub fn less_than(machine: &mut Machine) -> Result<(), ErrorType> {
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    if b < a {
-        machine.push(-1);
-    } else {
-        machine.push(0);
-    }
-
-    Ok(())
+    let body = create_word(machine, Some("2@"))?;
+    machine.allot(2 * CELL_SIZE as Cell)?;
+    machine.store(body as Cell, a)?;
+    machine.store(body as Cell + CELL_SIZE as Cell, b)
 }
 
-pub fn and(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn value(machine: &mut Machine) -> Result<(), ErrorType> {
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    let b = match machine.pop() {
+
+    let name = machine.parse_name()?;
+    align(machine)?;
+    let body = machine.here();
+    machine.allot(CELL_SIZE as Cell)?;
+    machine.store(body as Cell, a)?;
+    machine.define_checked(&name, Function::Value(body))?;
+    Ok(())
+}
+
+// Parse the name of a value and get the address of its data field.
+pub fn parse_value(machine: &mut Machine) -> Result<usize, ErrorType> {
+    let name = machine.parse_name()?;
+    let xt = match machine.names.get(&name) {
+        Some(xt) => *xt,
+        None => {
+            machine.print(&format!("{}?\n", name))?;
+            return Err(ErrorType::WordNotFound);
+        }
+    };
+
+    match machine.dictionary[xt].function {
+        Function::Value(body) => Ok(body),
+        _ => Err(ErrorType::CompilationError),
+    }
+}
+
+pub fn to(machine: &mut Machine) -> Result<(), ErrorType> {
+    let body = parse_value(machine)?;
+    if machine.compiling() {
+        machine.compile_instruction(Instruction::Literal(body as Cell))?;
+        machine.compile_instruction(Instruction::Call(machine.builtins["!"]))?;
+        return Ok(());
+    }
+
+    let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    machine.push(a & b);
+    machine.store(body as Cell, a)
+}
+
+pub fn paren_does(machine: &mut Machine) -> Result<(), ErrorType> {
+    let does = inline_literal(machine)?;
+    machine.pc += 1;
+
+    match machine.dictionary.last_mut() {
+        Some(Entry { function: Function::Created { does: behavior, .. }, .. }) => {
+            *behavior = Some(does as usize);
+            Ok(())
+        },
+        _ => Err(ErrorType::CompilationError),
+    }
+}
+
+pub fn immediate(machine: &mut Machine) -> Result<(), ErrorType> {
+    match machine.dictionary.last_mut() {
+        Some(entry) => {
+            entry.immediate = true;
+            Ok(())
+        },
+        None => Err(ErrorType::CompilationError),
+    }
+}
 
+pub fn state(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = machine.state;
+    machine.push(addr)?;
     Ok(())
 }
 
-pub fn or(machine: &mut Machine) -> Result<(), ErrorType> {
-    let a = match machine.pop() {
+pub fn compile_comma(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
-    let b = match machine.pop() {
+
+    let xt = machine.execution_token(xt)?;
+    machine.compile_instruction(Instruction::Call(xt))?;
+    Ok(())
+}
+
+pub fn left_bracket(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.set_compiling(false)
+}
+
+pub fn right_bracket(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.set_compiling(true)
+}
+
+pub fn literal(machine: &mut Machine) -> Result<(), ErrorType> {
+    if !machine.compiling() {
+        return Err(ErrorType::OutsideCompileMode);
+    }
+
+    let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    machine.push(a | b);
+    machine.compile_instruction(Instruction::Literal(a))?;
+    Ok(())
+}
+
+// Parse a word name while compiling and get its execution token.
+fn parse_compiled_xt(machine: &mut Machine) -> Result<usize, ErrorType> {
+    if !machine.compiling() {
+        return Err(ErrorType::OutsideCompileMode);
+    }
+
+    let name = machine.parse_name()?;
+    match machine.names.get(&name) {
+        Some(xt) => Ok(*xt),
+        None => {
+            machine.print(&format!("undefined word: {}\n", name))?;
+            Err(ErrorType::CompilationError)
+        }
+    }
+}
+
+pub fn postpone(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = parse_compiled_xt(machine)?;
 
+    // Immediate words run when the current definition does, anything else
+    // is compiled into whatever definition is being built at that point.
+    if machine.dictionary[xt].immediate {
+        machine.compile_instruction(Instruction::Call(xt))?;
+    } else {
+        machine.compile_instruction(Instruction::Literal(xt as Cell))?;
+        machine.compile_instruction(Instruction::Call(machine.builtins["compile,"]))?;
+    }
     Ok(())
 }
 
-pub fn invert(machine: &mut Machine) -> Result<(), ErrorType> {
-    let a = match machine.pop() {
+pub fn bracket_compile(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = parse_compiled_xt(machine)?;
+    machine.compile_instruction(Instruction::Call(xt))?;
+    Ok(())
+}
+
+pub fn bracket_tick(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = parse_compiled_xt(machine)?;
+    machine.compile_instruction(Instruction::Literal(xt as Cell))?;
+    Ok(())
+}
+
+// Parse a word name and get its execution token.
+fn parse_xt(machine: &mut Machine) -> Result<usize, ErrorType> {
+    let name = machine.parse_name()?;
+    match machine.names.get(&name) {
+        Some(xt) => Ok(*xt),
+        None => {
+            machine.print(&format!("{}?\n", name))?;
+            Err(ErrorType::WordNotFound)
+        }
+    }
+}
+
+pub fn tick(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = parse_xt(machine)?;
+    machine.push(xt as Cell)?;
+    Ok(())
+}
+
+pub fn execute(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    machine.push(!a);
-
+    // Let invoke run the word, so chains of execute don't nest.
+    machine.handoff = Some(machine.execution_token(xt)?);
     Ok(())
 }
 
-pub fn clearstack(machine: &mut Machine) -> Result<(), ErrorType> {
-    machine.stack.clear();
+pub fn defer(machine: &mut Machine) -> Result<(), ErrorType> {
+    let name = machine.parse_name()?;
+    machine.define_checked(&name, Function::Deferred(None))?;
     Ok(())
 }
 
-pub fn branch0(machine: &mut Machine) -> Result<(), ErrorType> {
-    let a = match machine.pop() {
+// Get the execution token of a deferred word.
+fn deferred(machine: &Machine, xt: usize) -> Result<usize, ErrorType> {
+    match machine.dictionary[xt].function {
+        Function::Deferred(_) => Ok(xt),
+        _ => Err(ErrorType::CompilationError),
+    }
+}
+
+pub fn defer_fetch(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    if a != 0 {
-        machine.pc += 1;
-        return Ok(());
+    let xt = machine.execution_token(xt)?;
+    match machine.dictionary[deferred(machine, xt)?].function {
+        Function::Deferred(Some(action)) => {
+            machine.push(action as Cell)?;
+            Ok(())
+        },
+        _ => Err(ErrorType::UninitializedDeferred),
     }
+}
 
-    if machine.pc >= machine.data.len() {
-        return Err(ErrorType::BranchOutOfBounds);
+pub fn defer_store(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let action = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+
+    let xt = deferred(machine, machine.execution_token(xt)?)?;
+    let action = machine.execution_token(action)?;
+    machine.dictionary[xt].function = Function::Deferred(Some(action));
+    Ok(())
+}
+
+pub fn is(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = parse_xt(machine)?;
+    let xt = deferred(machine, xt)?;
+    if machine.compiling() {
+        machine.compile_instruction(Instruction::Literal(xt as Cell))?;
+        machine.compile_instruction(Instruction::Call(machine.builtins["defer!"]))?;
+        return Ok(());
     }
 
-    if let Value::Number(n) = machine.data[machine.pc] {
-        machine.pc += n as usize;
-    } else {
-        return Err(ErrorType::InvalidOffset);
+    machine.push(xt as Cell)?;
+    defer_store(machine)
+}
+
+pub fn action_of(machine: &mut Machine) -> Result<(), ErrorType> {
+    let xt = parse_xt(machine)?;
+    let xt = deferred(machine, xt)?;
+    if machine.compiling() {
+        machine.compile_instruction(Instruction::Literal(xt as Cell))?;
+        machine.compile_instruction(Instruction::Call(machine.builtins["defer@"]))?;
+        return Ok(());
     }
 
+    machine.push(xt as Cell)?;
+    defer_fetch(machine)
+}
+
+pub fn to_in(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = machine.to_in;
+    machine.push(addr)?;
     Ok(())
 }
 
-pub fn branch(machine: &mut Machine) -> Result<(), ErrorType> {
-    if machine.pc >= machine.data.len() {
-        return Err(ErrorType::BranchOutOfBounds);
-    }
+pub fn source(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = machine.input_buffer;
+    machine.push(addr)?;
+    machine.push(machine.input_len as Cell)?;
+    Ok(())
+}
 
-    if let Value::Number(n) = machine.data[machine.pc] {
-        machine.pc += n as usize;
-    } else {
-        return Err(ErrorType::InvalidOffset);
+pub fn paren(machine: &mut Machine) -> Result<(), ErrorType> {
+    // A comment left open at the end of a line carries on into the next.
+    while !machine.parse(|c| c == b')').1 {
+        if !machine.refill()? {
+            break;
+        }
     }
+    Ok(())
+}
 
+pub fn backslash(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.parse(|_| false);
     Ok(())
 }
 
-pub fn to_r(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn emit(machine: &mut Machine) -> Result<(), ErrorType> {
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    machine.return_stack.push(a as usize);
-    Ok(())
+    machine.write(&[a as u8])
 }
 
-pub fn from_r(machine: &mut Machine) -> Result<(), ErrorType> {
-    let a = match machine.return_stack.pop() {
+pub fn type_(machine: &mut Machine) -> Result<(), ErrorType> {
+    let len = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let addr = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    machine.push(a as i32);
-    Ok(())
+    let text = machine.bytes(addr, len)?.to_vec();
+    machine.write(&text)
+}
+
+pub fn cr(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.print("\n")
+}
+
+pub fn space(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.print(" ")
 }
 
-pub fn if_(machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn spaces(machine: &mut Machine) -> Result<(), ErrorType> {
     let a = match machine.pop() {
         Some(n) => n,
         None => return Err(ErrorType::StackUnderflow)
     };
 
-    // Execute the if branch.
-    if a != 0 {
-        return Ok(());
+    write_spaces(machine, a.max(0) as usize)
+}
+
+// Write `n` spaces a buffer at a time, so a huge count can't exhaust memory.
+fn write_spaces(machine: &mut Machine, mut n: usize) -> Result<(), ErrorType> {
+    const BLANKS: [u8; 64] = [b' '; 64];
+    while n > 0 {
+        let len = n.min(BLANKS.len());
+        machine.write(&BLANKS[..len])?;
+        n -= len;
     }
+    Ok(())
+}
 
-    // Find our matching then or else.
-    let mut ifs = 0;
-    while machine.pc < machine.data.len() {
-        if let Value::Word(w) = &machine.data[machine.pc] {
-            if w == "then" && ifs == 0 {
-                return Ok(());
-            } else if w == "else" && ifs == 0 {
-                machine.control_flow_stack.push(0);
-                return Ok(());
-            } else if w == "if" {
-                ifs += 1;
-                machine.pc += 1;
-            } else if w == "then" && ifs != 0 {
-                ifs -= 1;
-                machine.pc += 1;
-            } else {
-                machine.pc += 1;
-            }
-        } else {
-            machine.pc += 1;
-        }
+pub fn bl(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.push(b' ' as Cell)?;
+    Ok(())
+}
+
+pub fn char(machine: &mut Machine) -> Result<(), ErrorType> {
+    let name = machine.parse_name()?;
+    machine.push(name.as_bytes()[0] as Cell)?;
+    Ok(())
+}
+
+pub fn bracket_char(machine: &mut Machine) -> Result<(), ErrorType> {
+    if !machine.compiling() {
+        return Err(ErrorType::OutsideCompileMode);
     }
 
-    Err(ErrorType::UnbalancedControl)
+    let name = machine.parse_name()?;
+    machine.compile_instruction(Instruction::Literal(name.as_bytes()[0] as Cell))?;
+    Ok(())
+}
+
+pub fn key(machine: &mut Machine) -> Result<(), ErrorType> {
+    // End of input reads as -1.
+    match machine.read_byte()? {
+        Some(c) => machine.push(c as Cell),
+        None => machine.push(-1),
+    }
 }
 
-pub fn then(_machine: &mut Machine) -> Result<(), ErrorType> {
+pub fn key_question(machine: &mut Machine) -> Result<(), ErrorType> {
+    let ready = machine.input_ready()?;
+    machine.push(if ready { -1 } else { 0 })?;
     Ok(())
 }
 
-pub fn else_(machine: &mut Machine) -> Result<(), ErrorType> {
-    let a = match machine.control_flow_stack.pop() {
+pub fn accept(machine: &mut Machine) -> Result<(), ErrorType> {
+    let max = match machine.pop() {
+        Some(n) => n,
+        None => return Err(ErrorType::StackUnderflow)
+    };
+    let addr = match machine.pop() {
         Some(n) => n,
-        None => 1,
+        None => return Err(ErrorType::StackUnderflow)
     };
 
-    // Execute the else branch.
-    if a == 0 {
-        return Ok(());
+    let line = machine.read_line()?.unwrap_or_default();
+    let len = line.len().min(max.max(0) as usize);
+    for (i, c) in line.bytes().take(len).enumerate() {
+        machine.store_byte(addr.wrapping_add(i as Cell), c)?;
     }
+    machine.push(len as Cell)?;
+    Ok(())
+}
 
-    // Find our matching 'then'.
-    let mut ifs = 0;
-    while machine.pc < machine.data.len() {
-        if let Value::Word(w) = &machine.data[machine.pc] {
-            if w == "then" && ifs == 0 {
-                return Ok(());
-            } else if w == "if" {
-                ifs += 1;
-                machine.pc += 1;
-            } else if w == "then" && ifs != 0 {
-                ifs -= 1;
-                machine.pc += 1;
-            } else {
-                machine.pc += 1;
-            }
-        } else {
-            machine.pc += 1;
-        }
-    }
+pub fn base(machine: &mut Machine) -> Result<(), ErrorType> {
+    let addr = machine.base;
+    machine.push(addr)?;
+    Ok(())
+}
+
+pub fn hex(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.store(machine.base, 16)
+}
+
+pub fn decimal(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.store(machine.base, 10)
+}
 
-    Err(ErrorType::UnbalancedControl)
+pub fn binary(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.store(machine.base, 2)
 }
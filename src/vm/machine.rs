@@ -1,258 +1,1401 @@
 use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::fmt;
+use std::io;
+use std::io::prelude::*;
 
+#[cfg(feature = "float")]
+use crate::vm::float;
 use crate::vm::instructions;
 use crate::vm::ErrorType;
-use crate::vm::Value;
+use crate::vm::Cell;
+use crate::vm::DCell;
+use crate::vm::CELL_SIZE;
+#[cfg(feature = "float")]
+use crate::vm::FLOAT_SIZE;
+use crate::vm::HOLD_SIZE;
+use crate::vm::Instruction;
+use crate::vm::INPUT_SIZE;
+use crate::vm::UCell;
+use crate::vm::UDCell;
 
 pub enum Function {
-    Builtin(fn(&mut Machine) -> Result<(), ErrorType>),
-    UserDefined(Vec<Value>),
+    /// Native code, along with how many items it takes off the stacks.
+    Builtin(fn(&mut Machine) -> Result<(), ErrorType>, Arity),
+    UserDefined(Vec<Instruction>),
+    /// Pushes the address of its data field, then runs the `does` word.
+    Created { body: usize, does: Option<usize> },
+    /// Pushes the cell stored at its data field, which `to` can change.
+    Value(usize),
+    /// Runs whichever word `is` last assigned to it.
+    Deferred(Option<usize>),
     Action,
 }
 
+/// How many items a builtin takes from the data stack and, with the float
+/// feature, from the float stack. The machine checks these before invoking
+/// it, so a builtin never fails partway through popping its arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub cells: usize,
+    #[cfg(feature = "float")]
+    pub floats: usize,
+}
+
+impl Arity {
+    pub const fn cells(cells: usize) -> Arity {
+        Arity {
+            cells,
+            #[cfg(feature = "float")]
+            floats: 0,
+        }
+    }
+
+    #[cfg(feature = "float")]
+    pub const fn floats(floats: usize) -> Arity {
+        Arity { cells: 0, floats }
+    }
+
+    #[cfg(feature = "float")]
+    pub const fn mixed(cells: usize, floats: usize) -> Arity {
+        Arity { cells, floats }
+    }
+}
+
 impl fmt::Debug for Function {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Function")
     }
 }
 
+#[derive(Debug)]
+pub struct Entry {
+    pub name: String,
+    pub function: Function,
+    pub immediate: bool,
+}
+
+/// An input stream that can tell whether a read would have to wait.
+pub trait Input: BufRead {
+    /// Whether input can be read without waiting for more to arrive.
+    fn ready(&self) -> bool;
+}
+
+impl<R: Read> Input for io::BufReader<R> {
+    fn ready(&self) -> bool {
+        !self.buffer().is_empty()
+    }
+}
+
+impl<T: AsRef<[u8]>> Input for io::Cursor<T> {
+    fn ready(&self) -> bool {
+        self.position() < self.get_ref().as_ref().len() as u64
+    }
+}
+
+impl Input for &'static [u8] {
+    fn ready(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+// Stdin can't say what it holds without reading, so it never claims input.
+impl Input for io::StdinLock<'static> {
+    fn ready(&self) -> bool {
+        false
+    }
+}
+
+/// Where the machine reads input for words like `key` and writes output.
+pub struct Streams {
+    pub input: Box<dyn Input>,
+    pub output: Box<dyn Write>,
+}
+
+impl fmt::Debug for Streams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Streams")
+    }
+}
+
+/// The caller state saved when entering a definition.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub code: Option<usize>,
+    pub pc: usize,
+    pub loops: usize,
+}
+
 #[derive(Debug)]
 pub struct Machine {
-    pub compile_mode: bool,
-    pub compile_buffer: Vec<Value>,
-    pub dictionary: HashMap<String, Function>,
-    pub stack: Vec<i32>,
-    pub return_stack: Vec<usize>,
-    pub control_flow_stack: Vec<i32>,
+    pub state: Cell,
+    pub base: Cell,
+    pub compile_name: String,
+    pub compile_buffer: Vec<Instruction>,
+    pub dictionary: Vec<Entry>,
+    pub names: HashMap<String, usize>,
+    /// The words the machine starts with, which compiled code calls
+    /// whatever the user later redefines.
+    pub builtins: HashMap<String, usize>,
+    pub memory: Vec<u8>,
+    pub stack: Vec<Cell>,
+    #[cfg(feature = "float")]
+    pub float_stack: Vec<f64>,
+    pub return_stack: Vec<Cell>,
+    pub loop_stack: Vec<(Cell, Cell)>,
+    pub frames: Vec<Frame>,
+    pub max_call_depth: usize,
+    /// A word a builtin such as `execute` asked to run in its place.
+    pub handoff: Option<usize>,
+    pub max_stack: usize,
+    pub max_return_stack: usize,
+    pub max_data_space: usize,
+    pub max_dictionary: usize,
+    pub max_definition: usize,
+    pub floored: bool,
+    pub code: Option<usize>,
     pub pc: usize,
-    pub data: Vec<Value>,
-    pub context: Vec<Box<Vec<Value>>>,
+    pub input_buffer: Cell,
+    pub input_len: usize,
+    pub hold_area: Cell,
+    pub hold: Cell,
+    pub to_in: Cell,
+    // Where the space the machine reserves for itself ends.
+    reserved: usize,
+    pub pending: VecDeque<String>,
+    pub streams: Streams,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Machine {
     pub fn new() -> Machine {
-        let mut dictionary = HashMap::new();
-        dictionary.insert(String::from(":"), Function::Builtin(compile));
-        dictionary.insert(String::from(";"), Function::Builtin(finish_compile));
-        dictionary.insert(String::from("+"), Function::Builtin(instructions::add));
-        dictionary.insert(String::from("-"), Function::Builtin(instructions::sub));
-        dictionary.insert(String::from("*"), Function::Builtin(instructions::mult));
-        dictionary.insert(String::from("/"), Function::Builtin(instructions::div));
-        dictionary.insert(String::from("mod"), Function::Builtin(instructions::mod_));
-        dictionary.insert(String::from("dup"), Function::Builtin(instructions::dup));
-        dictionary.insert(String::from("drop"), Function::Builtin(instructions::drop));
-        dictionary.insert(String::from("swap"), Function::Builtin(instructions::swap));
-        dictionary.insert(String::from("over"), Function::Builtin(instructions::over));
-        dictionary.insert(String::from("rot"), Function::Builtin(instructions::rot));
-        dictionary.insert(String::from("."), Function::Builtin(instructions::dot));
-        dictionary.insert(String::from(".s"), Function::Builtin(instructions::sdot));
-        dictionary.insert(String::from(".\""), Function::Builtin(instructions::dot_quote));
-        dictionary.insert(String::from("="), Function::Builtin(instructions::eq));
-        dictionary.insert(String::from(">"), Function::Builtin(instructions::greater_than));
-        dictionary.insert(String::from("<"), Function::Builtin(instructions::less_than));
-        dictionary.insert(String::from("and"), Function::Builtin(instructions::and));
-        dictionary.insert(String::from("or"), Function::Builtin(instructions::or));
-        dictionary.insert(String::from("invert"), Function::Builtin(instructions::invert));
-        dictionary.insert(String::from("clearstack"), Function::Builtin(instructions::clearstack));
-        dictionary.insert(String::from("0branch"), Function::Builtin(instructions::branch0));
-        dictionary.insert(String::from("branch"), Function::Builtin(instructions::branch));
-        dictionary.insert(String::from(">r"), Function::Builtin(instructions::to_r));
-        dictionary.insert(String::from("r>"), Function::Builtin(instructions::from_r));
-
-        dictionary.insert(String::from("if"), Function::Builtin(instructions::if_));
-        dictionary.insert(String::from("then"), Function::Builtin(instructions::then));
-        dictionary.insert(String::from("else"), Function::Builtin(instructions::else_));
-        dictionary.insert(String::from("do"), Function::Action);
-        dictionary.insert(String::from("loop"), Function::Action);
-
-        Machine {
-            compile_mode: false,
+        Machine::with_streams(io::BufReader::new(io::stdin()), io::stdout())
+    }
+
+    /// Create a machine that does all of its I/O through the given streams.
+    pub fn with_streams<R, W>(input: R, output: W) -> Machine
+    where
+        R: Input + 'static,
+        W: Write + 'static,
+    {
+        let mut machine = Machine {
+            state: 0,
+            base: 0,
+            compile_name: String::new(),
             compile_buffer: Vec::new(),
-            dictionary,
+            dictionary: Vec::new(),
+            names: HashMap::new(),
+            builtins: HashMap::new(),
+            memory: Vec::new(),
             stack: Vec::new(),
+            #[cfg(feature = "float")]
+            float_stack: Vec::new(),
             return_stack: Vec::new(),
-            control_flow_stack: Vec::new(),
+            loop_stack: Vec::new(),
+            frames: Vec::new(),
+            max_call_depth: 4096,
+            handoff: None,
+            max_stack: 65536,
+            max_return_stack: 65536,
+            // Addresses and execution tokens have to fit in a cell.
+            max_data_space: (1 << 24).min(Cell::MAX as usize),
+            max_dictionary: 65536.min(Cell::MAX as usize),
+            max_definition: 65536,
+            floored: false,
+            code: None,
             pc: 0,
-            data: Vec::new(),
-            context: Vec::new(),
+            input_buffer: 0,
+            input_len: 0,
+            hold_area: 0,
+            reserved: 0,
+            hold: 0,
+            to_in: 0,
+            pending: VecDeque::new(),
+            streams: Streams { input: Box::new(input), output: Box::new(output) },
+        };
+
+        // The start of data space holds STATE, BASE, >IN, the input buffer
+        // and the area pictured numeric output is built in.
+        machine.state = machine.reserve(CELL_SIZE);
+        machine.base = machine.reserve(CELL_SIZE);
+        let _ = machine.store(machine.base, 10);
+        machine.to_in = machine.reserve(CELL_SIZE);
+        machine.input_buffer = machine.reserve(INPUT_SIZE);
+        machine.hold_area = machine.reserve(HOLD_SIZE);
+        machine.hold = machine.hold_area + HOLD_SIZE as Cell;
+        machine.reserved = machine.memory.len();
+
+        machine.define(":", Function::Builtin(compile, Arity::cells(0)));
+        machine.define_immediate(";", Function::Builtin(finish_compile, Arity::cells(0)));
+        machine.define("+", Function::Builtin(instructions::add, Arity::cells(2)));
+        machine.define("-", Function::Builtin(instructions::sub, Arity::cells(2)));
+        machine.define("*", Function::Builtin(instructions::mult, Arity::cells(2)));
+        machine.define("/", Function::Builtin(instructions::div, Arity::cells(2)));
+        machine.define("mod", Function::Builtin(instructions::mod_, Arity::cells(2)));
+        machine.define("/mod", Function::Builtin(instructions::slash_mod, Arity::cells(2)));
+        machine.define("*/", Function::Builtin(instructions::star_slash, Arity::cells(3)));
+        machine.define("*/mod", Function::Builtin(instructions::star_slash_mod, Arity::cells(3)));
+        machine.define("um*", Function::Builtin(instructions::um_star, Arity::cells(2)));
+        machine.define("um/mod", Function::Builtin(instructions::um_slash_mod, Arity::cells(3)));
+        machine.define("fm/mod", Function::Builtin(instructions::fm_slash_mod, Arity::cells(3)));
+        machine.define("sm/rem", Function::Builtin(instructions::sm_slash_rem, Arity::cells(3)));
+        machine.define("dup", Function::Builtin(instructions::dup, Arity::cells(1)));
+        machine.define("drop", Function::Builtin(instructions::drop, Arity::cells(1)));
+        machine.define("swap", Function::Builtin(instructions::swap, Arity::cells(2)));
+        machine.define("over", Function::Builtin(instructions::over, Arity::cells(2)));
+        machine.define("rot", Function::Builtin(instructions::rot, Arity::cells(3)));
+        machine.define("nip", Function::Builtin(instructions::nip, Arity::cells(2)));
+        machine.define("tuck", Function::Builtin(instructions::tuck, Arity::cells(2)));
+        machine.define("-rot", Function::Builtin(instructions::minus_rot, Arity::cells(3)));
+        machine.define("?dup", Function::Builtin(instructions::question_dup, Arity::cells(1)));
+        machine.define("pick", Function::Builtin(instructions::pick, Arity::cells(1)));
+        machine.define("roll", Function::Builtin(instructions::roll, Arity::cells(1)));
+        machine.define("depth", Function::Builtin(instructions::depth, Arity::cells(0)));
+        machine.define("2dup", Function::Builtin(instructions::two_dup, Arity::cells(2)));
+        machine.define("2drop", Function::Builtin(instructions::two_drop, Arity::cells(2)));
+        machine.define("2swap", Function::Builtin(instructions::two_swap, Arity::cells(4)));
+        machine.define("2over", Function::Builtin(instructions::two_over, Arity::cells(4)));
+        machine.define("2rot", Function::Builtin(instructions::two_rot, Arity::cells(6)));
+        machine.define("negate", Function::Builtin(instructions::negate, Arity::cells(1)));
+        machine.define("abs", Function::Builtin(instructions::abs, Arity::cells(1)));
+        machine.define("min", Function::Builtin(instructions::min, Arity::cells(2)));
+        machine.define("max", Function::Builtin(instructions::max, Arity::cells(2)));
+        machine.define("1+", Function::Builtin(instructions::one_plus, Arity::cells(1)));
+        machine.define("1-", Function::Builtin(instructions::one_minus, Arity::cells(1)));
+        machine.define("2*", Function::Builtin(instructions::two_star, Arity::cells(1)));
+        machine.define("2/", Function::Builtin(instructions::two_slash, Arity::cells(1)));
+        machine.define("lshift", Function::Builtin(instructions::lshift, Arity::cells(2)));
+        machine.define("rshift", Function::Builtin(instructions::rshift, Arity::cells(2)));
+        machine.define(".", Function::Builtin(instructions::dot, Arity::cells(1)));
+        machine.define(".s", Function::Builtin(instructions::sdot, Arity::cells(0)));
+        machine.define_immediate(".\"", Function::Builtin(instructions::dot_quote, Arity::cells(0)));
+        machine.define("emit", Function::Builtin(instructions::emit, Arity::cells(1)));
+        machine.define("type", Function::Builtin(instructions::type_, Arity::cells(2)));
+        machine.define("cr", Function::Builtin(instructions::cr, Arity::cells(0)));
+        machine.define("space", Function::Builtin(instructions::space, Arity::cells(0)));
+        machine.define("spaces", Function::Builtin(instructions::spaces, Arity::cells(1)));
+        machine.define("bl", Function::Builtin(instructions::bl, Arity::cells(0)));
+        machine.define("char", Function::Builtin(instructions::char, Arity::cells(0)));
+        machine.define_immediate("[char]", Function::Builtin(instructions::bracket_char, Arity::cells(0)));
+        machine.define("key", Function::Builtin(instructions::key, Arity::cells(0)));
+        machine.define("key?", Function::Builtin(instructions::key_question, Arity::cells(0)));
+        machine.define("accept", Function::Builtin(instructions::accept, Arity::cells(2)));
+        machine.define_immediate("s\"", Function::Builtin(instructions::s_quote, Arity::cells(0)));
+        machine.define_immediate("s\\\"", Function::Builtin(instructions::s_backslash_quote, Arity::cells(0)));
+        machine.define_immediate("c\"", Function::Builtin(instructions::c_quote, Arity::cells(0)));
+        machine.define("count", Function::Builtin(instructions::count, Arity::cells(1)));
+        machine.define("=", Function::Builtin(instructions::eq, Arity::cells(2)));
+        machine.define(">", Function::Builtin(instructions::greater_than, Arity::cells(2)));
+        machine.define("<", Function::Builtin(instructions::less_than, Arity::cells(2)));
+        machine.define("and", Function::Builtin(instructions::and, Arity::cells(2)));
+        machine.define("or", Function::Builtin(instructions::or, Arity::cells(2)));
+        machine.define("invert", Function::Builtin(instructions::invert, Arity::cells(1)));
+        machine.define("xor", Function::Builtin(instructions::xor, Arity::cells(2)));
+        machine.define("<>", Function::Builtin(instructions::not_eq, Arity::cells(2)));
+        machine.define("u<", Function::Builtin(instructions::u_less_than, Arity::cells(2)));
+        machine.define("u>", Function::Builtin(instructions::u_greater_than, Arity::cells(2)));
+        machine.define("0=", Function::Builtin(instructions::zero_eq, Arity::cells(1)));
+        machine.define("0<", Function::Builtin(instructions::zero_less_than, Arity::cells(1)));
+        machine.define("0>", Function::Builtin(instructions::zero_greater_than, Arity::cells(1)));
+        machine.define("within", Function::Builtin(instructions::within, Arity::cells(3)));
+        machine.define("clearstack", Function::Builtin(instructions::clearstack, Arity::cells(0)));
+        machine.define("0branch", Function::Builtin(instructions::branch0, Arity::cells(1)));
+        machine.define("branch", Function::Builtin(instructions::branch, Arity::cells(0)));
+        machine.define(">r", Function::Builtin(instructions::to_r, Arity::cells(1)));
+        machine.define("r>", Function::Builtin(instructions::from_r, Arity::cells(0)));
+        machine.define("r@", Function::Builtin(instructions::r_fetch, Arity::cells(0)));
+        machine.define("2>r", Function::Builtin(instructions::two_to_r, Arity::cells(2)));
+        machine.define("2r>", Function::Builtin(instructions::two_from_r, Arity::cells(0)));
+        machine.define("2r@", Function::Builtin(instructions::two_r_fetch, Arity::cells(0)));
+
+        machine.define("here", Function::Builtin(instructions::here, Arity::cells(0)));
+        machine.define("allot", Function::Builtin(instructions::allot, Arity::cells(1)));
+        machine.define(",", Function::Builtin(instructions::comma, Arity::cells(1)));
+        machine.define("c,", Function::Builtin(instructions::c_comma, Arity::cells(1)));
+        machine.define("align", Function::Builtin(instructions::align, Arity::cells(0)));
+        machine.define("aligned", Function::Builtin(instructions::aligned, Arity::cells(1)));
+        machine.define("@", Function::Builtin(instructions::fetch, Arity::cells(1)));
+        machine.define("!", Function::Builtin(instructions::store, Arity::cells(2)));
+        machine.define("c@", Function::Builtin(instructions::c_fetch, Arity::cells(1)));
+        machine.define("c!", Function::Builtin(instructions::c_store, Arity::cells(2)));
+        machine.define("+!", Function::Builtin(instructions::plus_store, Arity::cells(2)));
+        machine.define("cells", Function::Builtin(instructions::cells, Arity::cells(1)));
+        machine.define("cell+", Function::Builtin(instructions::cell_plus, Arity::cells(1)));
+        machine.define("chars", Function::Builtin(instructions::chars, Arity::cells(1)));
+        machine.define("char+", Function::Builtin(instructions::char_plus, Arity::cells(1)));
+
+        machine.define("2@", Function::Builtin(instructions::two_fetch, Arity::cells(1)));
+        machine.define("2!", Function::Builtin(instructions::two_store, Arity::cells(3)));
+        machine.define("create", Function::Builtin(instructions::create, Arity::cells(0)));
+        machine.define("variable", Function::Builtin(instructions::variable, Arity::cells(0)));
+        machine.define("2variable", Function::Builtin(instructions::two_variable, Arity::cells(0)));
+        machine.define("constant", Function::Builtin(instructions::constant, Arity::cells(1)));
+        machine.define("2constant", Function::Builtin(instructions::two_constant, Arity::cells(2)));
+        machine.define("value", Function::Builtin(instructions::value, Arity::cells(1)));
+        machine.define_immediate("to", Function::Builtin(instructions::to, Arity::cells(0)));
+        machine.define("does>", Function::Action);
+        machine.define("(does>)", Function::Builtin(instructions::paren_does, Arity::cells(0)));
+        machine.define("'", Function::Builtin(instructions::tick, Arity::cells(0)));
+        machine.define("execute", Function::Builtin(instructions::execute, Arity::cells(1)));
+        machine.define("defer", Function::Builtin(instructions::defer, Arity::cells(0)));
+        machine.define("defer@", Function::Builtin(instructions::defer_fetch, Arity::cells(1)));
+        machine.define("defer!", Function::Builtin(instructions::defer_store, Arity::cells(2)));
+        machine.define_immediate("is", Function::Builtin(instructions::is, Arity::cells(0)));
+        machine.define_immediate("action-of", Function::Builtin(instructions::action_of, Arity::cells(0)));
+        machine.define(":noname", Function::Builtin(compile_noname, Arity::cells(0)));
+        machine.define("u.", Function::Builtin(instructions::u_dot, Arity::cells(1)));
+        machine.define(".r", Function::Builtin(instructions::dot_r, Arity::cells(2)));
+        machine.define("u.r", Function::Builtin(instructions::u_dot_r, Arity::cells(2)));
+        machine.define("d.", Function::Builtin(instructions::d_dot, Arity::cells(2)));
+        machine.define("d+", Function::Builtin(instructions::d_plus, Arity::cells(4)));
+        machine.define("d-", Function::Builtin(instructions::d_minus, Arity::cells(4)));
+        machine.define("dnegate", Function::Builtin(instructions::d_negate, Arity::cells(2)));
+        machine.define("dabs", Function::Builtin(instructions::d_abs, Arity::cells(2)));
+        machine.define("d<", Function::Builtin(instructions::d_less_than, Arity::cells(4)));
+        machine.define("d=", Function::Builtin(instructions::d_eq, Arity::cells(4)));
+        machine.define("d>s", Function::Builtin(instructions::d_to_s, Arity::cells(2)));
+        machine.define("s>d", Function::Builtin(instructions::s_to_d, Arity::cells(1)));
+        machine.define("m*", Function::Builtin(instructions::m_star, Arity::cells(2)));
+        machine.define("m+", Function::Builtin(instructions::m_plus, Arity::cells(3)));
+        machine.define("<#", Function::Builtin(instructions::less_number_sign, Arity::cells(0)));
+        machine.define("#", Function::Builtin(instructions::number_sign, Arity::cells(2)));
+        machine.define("#s", Function::Builtin(instructions::number_sign_s, Arity::cells(2)));
+        machine.define("#>", Function::Builtin(instructions::number_sign_greater, Arity::cells(2)));
+        machine.define("hold", Function::Builtin(instructions::hold, Arity::cells(1)));
+        machine.define("holds", Function::Builtin(instructions::holds, Arity::cells(2)));
+        machine.define("sign", Function::Builtin(instructions::sign, Arity::cells(1)));
+        #[cfg(feature = "float")]
+        {
+            machine.define("f+", Function::Builtin(float::f_plus, Arity::floats(2)));
+            machine.define("f-", Function::Builtin(float::f_minus, Arity::floats(2)));
+            machine.define("f*", Function::Builtin(float::f_star, Arity::floats(2)));
+            machine.define("f/", Function::Builtin(float::f_slash, Arity::floats(2)));
+            machine.define("f.", Function::Builtin(float::f_dot, Arity::floats(1)));
+            machine.define("fdup", Function::Builtin(float::f_dup, Arity::floats(1)));
+            machine.define("fdrop", Function::Builtin(float::f_drop, Arity::floats(1)));
+            machine.define("fswap", Function::Builtin(float::f_swap, Arity::floats(2)));
+            machine.define("fover", Function::Builtin(float::f_over, Arity::floats(2)));
+            machine.define("f@", Function::Builtin(float::f_fetch, Arity::cells(1)));
+            machine.define("f!", Function::Builtin(float::f_store, Arity::mixed(1, 1)));
+            machine.define("fvariable", Function::Builtin(float::f_variable, Arity::cells(0)));
+            machine.define("fconstant", Function::Builtin(float::f_constant, Arity::floats(1)));
+            machine.define("f<", Function::Builtin(float::f_less_than, Arity::floats(2)));
+            machine.define("f>", Function::Builtin(float::f_greater_than, Arity::floats(2)));
+            machine.define("f=", Function::Builtin(float::f_eq, Arity::floats(2)));
+            machine.define("f0<", Function::Builtin(float::f_zero_less_than, Arity::floats(1)));
+            machine.define("f0=", Function::Builtin(float::f_zero_eq, Arity::floats(1)));
+            machine.define("fsqrt", Function::Builtin(float::f_sqrt, Arity::floats(1)));
+            machine.define("fsin", Function::Builtin(float::f_sin, Arity::floats(1)));
+            machine.define("fcos", Function::Builtin(float::f_cos, Arity::floats(1)));
+            machine.define("fexp", Function::Builtin(float::f_exp, Arity::floats(1)));
+            machine.define("fln", Function::Builtin(float::f_ln, Arity::floats(1)));
+            machine.define("s>f", Function::Builtin(float::s_to_f, Arity::cells(1)));
+            machine.define("f>s", Function::Builtin(float::f_to_s, Arity::floats(1)));
         }
+        machine.define("base", Function::Builtin(instructions::base, Arity::cells(0)));
+        machine.define("hex", Function::Builtin(instructions::hex, Arity::cells(0)));
+        machine.define("decimal", Function::Builtin(instructions::decimal, Arity::cells(0)));
+        machine.define("binary", Function::Builtin(instructions::binary, Arity::cells(0)));
+        machine.define(">in", Function::Builtin(instructions::to_in, Arity::cells(0)));
+        machine.define("source", Function::Builtin(instructions::source, Arity::cells(0)));
+        machine.define_immediate("(", Function::Builtin(instructions::paren, Arity::cells(0)));
+        machine.define_immediate("\\", Function::Builtin(instructions::backslash, Arity::cells(0)));
+        machine.define("immediate", Function::Builtin(instructions::immediate, Arity::cells(0)));
+        machine.define("state", Function::Builtin(instructions::state, Arity::cells(0)));
+        machine.define("compile,", Function::Builtin(instructions::compile_comma, Arity::cells(1)));
+        machine.define("]", Function::Builtin(instructions::right_bracket, Arity::cells(0)));
+        machine.define_immediate("[", Function::Builtin(instructions::left_bracket, Arity::cells(0)));
+        machine.define_immediate("literal", Function::Builtin(instructions::literal, Arity::cells(1)));
+        machine.define_immediate("postpone", Function::Builtin(instructions::postpone, Arity::cells(0)));
+        machine.define_immediate("[compile]", Function::Builtin(instructions::bracket_compile, Arity::cells(0)));
+        machine.define_immediate("[']", Function::Builtin(instructions::bracket_tick, Arity::cells(0)));
+
+        machine.define("exit", Function::Builtin(instructions::exit, Arity::cells(0)));
+        machine.define("recurse", Function::Action);
+
+        machine.define("if", Function::Action);
+        machine.define("else", Function::Action);
+        machine.define("then", Function::Action);
+        machine.define("do", Function::Action);
+        machine.define("?do", Function::Action);
+        machine.define("loop", Function::Action);
+        machine.define("+loop", Function::Action);
+        machine.define("leave", Function::Action);
+        machine.define("begin", Function::Action);
+        machine.define("until", Function::Action);
+        machine.define("while", Function::Action);
+        machine.define("repeat", Function::Action);
+        machine.define("again", Function::Action);
+        machine.define("case", Function::Action);
+        machine.define("of", Function::Action);
+        machine.define("endof", Function::Action);
+        machine.define("endcase", Function::Action);
+        machine.define("(of)", Function::Builtin(instructions::of, Arity::cells(2)));
+        machine.define("(do)", Function::Builtin(instructions::do_, Arity::cells(2)));
+        machine.define("(?do)", Function::Builtin(instructions::question_do, Arity::cells(2)));
+        machine.define("(loop)", Function::Builtin(instructions::loop_, Arity::cells(0)));
+        machine.define("(+loop)", Function::Builtin(instructions::plus_loop, Arity::cells(1)));
+        machine.define("(leave)", Function::Builtin(instructions::leave, Arity::cells(0)));
+        machine.define("unloop", Function::Builtin(instructions::unloop, Arity::cells(0)));
+        machine.define("i", Function::Builtin(instructions::i, Arity::cells(0)));
+        machine.define("j", Function::Builtin(instructions::j, Arity::cells(0)));
+
+        machine.builtins = machine.names.clone();
+        machine
+    }
+
+    /// Limit the data stack, and the float stack, to `n` items.
+    pub fn stack_limit(mut self, n: usize) -> Machine {
+        self.max_stack = n;
+        self
+    }
+
+    /// Limit the return stack to `n` cells.
+    pub fn return_stack_limit(mut self, n: usize) -> Machine {
+        self.max_return_stack = n;
+        self
+    }
+
+    /// Limit data space to `n` bytes, including what the machine reserves.
+    /// The limit never goes past the largest address a cell can hold.
+    pub fn data_space_limit(mut self, n: usize) -> Machine {
+        self.max_data_space = n.min(Cell::MAX as usize);
+        self
+    }
+
+    /// Limit the dictionary to `n` words, including the builtins. The limit
+    /// never goes past the largest execution token a cell can hold.
+    pub fn dictionary_limit(mut self, n: usize) -> Machine {
+        self.max_dictionary = n.min(Cell::MAX as usize);
+        self
     }
 
-    pub fn push(&mut self, input: i32) {
+    /// Limit each definition to `n` compiled instructions.
+    pub fn definition_limit(mut self, n: usize) -> Machine {
+        self.max_definition = n;
+        self
+    }
+
+    /// Limit how deeply definitions can call each other.
+    pub fn call_depth_limit(mut self, n: usize) -> Machine {
+        self.max_call_depth = n;
+        self
+    }
+
+    /// Make `/`, `mod`, `/mod`, `*/` and `*/mod` round quotients toward
+    /// negative infinity instead of toward zero. Division is symmetric by
+    /// default; `fm/mod` and `sm/rem` always round their own way.
+    pub fn floored_division(mut self, floored: bool) -> Machine {
+        self.floored = floored;
+        self
+    }
+
+    pub fn push(&mut self, input: Cell) -> Result<(), ErrorType> {
+        if self.stack.len() >= self.max_stack {
+            return Err(ErrorType::StackOverflow);
+        }
         self.stack.push(input);
+        Ok(())
     }
 
-    pub fn pop(&mut self) -> Option<i32> {
+    pub fn pop(&mut self) -> Option<Cell> {
         self.stack.pop()
     }
 
-    pub fn execute(&mut self, input: &Vec<Value>) -> Result<(), ErrorType> {
+    /// Check that the data stack holds at least `n` items, so a word can
+    /// fail before it has popped any of its arguments.
+    pub fn require(&self, n: usize) -> Result<(), ErrorType> {
+        if self.stack.len() < n {
+            return Err(ErrorType::StackUnderflow);
+        }
+        Ok(())
+    }
+
+    // Check that the stacks hold everything a builtin is about to take.
+    fn check_arity(&self, arity: Arity) -> Result<(), ErrorType> {
+        self.require(arity.cells)?;
+        #[cfg(feature = "float")]
+        if self.float_stack.len() < arity.floats {
+            return Err(ErrorType::FloatStackUnderflow);
+        }
+        Ok(())
+    }
+
+    /// How many items the word `xt` takes off the stacks, if it is a builtin.
+    pub fn arity(&self, xt: usize) -> Option<Arity> {
+        match self.dictionary.get(xt)?.function {
+            Function::Builtin(_, arity) => Some(arity),
+            _ => None,
+        }
+    }
+
+    /// Check that the return stack has room for `n` more cells.
+    pub fn return_stack_room(&self, n: usize) -> Result<(), ErrorType> {
+        if self.return_stack.len() + n > self.max_return_stack {
+            return Err(ErrorType::ReturnStackOverflow);
+        }
+        Ok(())
+    }
+
+    pub fn rpush(&mut self, input: Cell) -> Result<(), ErrorType> {
+        self.return_stack_room(1)?;
+        self.return_stack.push(input);
+        Ok(())
+    }
+
+    #[cfg(feature = "float")]
+    pub fn fpush(&mut self, input: f64) -> Result<(), ErrorType> {
+        if self.float_stack.len() >= self.max_stack {
+            return Err(ErrorType::FloatStackOverflow);
+        }
+        self.float_stack.push(input);
+        Ok(())
+    }
+
+    #[cfg(feature = "float")]
+    pub fn fpop(&mut self) -> Option<f64> {
+        self.float_stack.pop()
+    }
+
+    /// Write raw bytes to the output stream.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), ErrorType> {
+        self.streams.output.write_all(bytes).map_err(|_| ErrorType::IoError)
+    }
+
+    pub fn print(&mut self, text: &str) -> Result<(), ErrorType> {
+        self.write(text.as_bytes())
+    }
+
+    pub fn flush(&mut self) -> Result<(), ErrorType> {
+        self.streams.output.flush().map_err(|_| ErrorType::IoError)
+    }
+
+    /// Read a byte from the input stream, or None at its end.
+    pub fn read_byte(&mut self) -> Result<Option<u8>, ErrorType> {
+        self.flush()?;
+        let byte = match self.streams.input.fill_buf() {
+            Ok(buffer) => buffer.first().copied(),
+            Err(_) => return Err(ErrorType::IoError),
+        };
+        if byte.is_some() {
+            self.streams.input.consume(1);
+        }
+        Ok(byte)
+    }
+
+    /// Whether a byte can be read from the input stream without waiting.
+    /// This never blocks, so it reports false when no input has arrived yet
+    /// as well as at the end of the stream.
+    pub fn input_ready(&mut self) -> Result<bool, ErrorType> {
+        self.flush()?;
+        Ok(self.streams.input.ready())
+    }
+
+    /// Read a line from the input stream without its line ending, or None at
+    /// the end of the stream.
+    pub fn read_line(&mut self) -> Result<Option<String>, ErrorType> {
+        self.flush()?;
+        let mut line = String::new();
+        match self.streams.input.read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(line.trim_end_matches(['\n', '\r']).to_string())),
+            Err(_) => Err(ErrorType::IoError),
+        }
+    }
+
+    /// Add a new dictionary entry, shadowing any older entry with the same
+    /// name. Returns the execution token of the new entry.
+    pub fn define(&mut self, name: &str, function: Function) -> usize {
+        let xt = self.dictionary.len();
+        self.dictionary.push(Entry { name: name.to_string(), function, immediate: false });
+
+        // Nameless entries can only be reached by their execution token.
+        if !name.is_empty() {
+            self.names.insert(name.to_string(), xt);
+        }
+        xt
+    }
+
+    // Set aside data space for the machine's own use.
+    fn reserve(&mut self, len: usize) -> Cell {
+        let addr = self.here();
+        self.memory.resize(addr + len, 0);
+        addr as Cell
+    }
+
+    /// The address of the next free byte in data space.
+    pub fn here(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Reserve `n` bytes of data space, or release them if `n` is negative.
+    pub fn allot(&mut self, n: Cell) -> Result<(), ErrorType> {
+        let here = self.here() as isize + n as isize;
+        if here < self.reserved as isize {
+            return Err(ErrorType::InvalidAddress);
+        }
+        if here as usize > self.max_data_space {
+            return Err(ErrorType::DataSpaceOverflow);
+        }
+
+        self.memory.resize(here as usize, 0);
+        Ok(())
+    }
+
+    // Check that `len` bytes at `addr` lie within allotted data space.
+    fn address(&self, addr: Cell, len: usize) -> Result<usize, ErrorType> {
+        if addr < 0 || addr as usize + len > self.memory.len() {
+            return Err(ErrorType::InvalidAddress);
+        }
+        Ok(addr as usize)
+    }
+
+    pub fn fetch(&self, addr: Cell) -> Result<Cell, ErrorType> {
+        let at = self.address(addr, CELL_SIZE)?;
+        let mut bytes = [0; CELL_SIZE];
+        bytes.copy_from_slice(&self.memory[at..at + CELL_SIZE]);
+        Ok(Cell::from_le_bytes(bytes))
+    }
+
+    pub fn store(&mut self, addr: Cell, value: Cell) -> Result<(), ErrorType> {
+        let at = self.address(addr, CELL_SIZE)?;
+        self.memory[at..at + CELL_SIZE].copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    #[cfg(feature = "float")]
+    pub fn fetch_float(&self, addr: Cell) -> Result<f64, ErrorType> {
+        let at = self.address(addr, FLOAT_SIZE)?;
+        let mut bytes = [0; FLOAT_SIZE];
+        bytes.copy_from_slice(&self.memory[at..at + FLOAT_SIZE]);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    #[cfg(feature = "float")]
+    pub fn store_float(&mut self, addr: Cell, value: f64) -> Result<(), ErrorType> {
+        let at = self.address(addr, FLOAT_SIZE)?;
+        self.memory[at..at + FLOAT_SIZE].copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    /// Borrow `len` bytes of data space starting at `addr`.
+    pub fn bytes(&self, addr: Cell, len: Cell) -> Result<&[u8], ErrorType> {
+        if len < 0 {
+            return Err(ErrorType::InvalidAddress);
+        }
+
+        let at = self.address(addr, len as usize)?;
+        Ok(&self.memory[at..at + len as usize])
+    }
+
+    pub fn fetch_byte(&self, addr: Cell) -> Result<u8, ErrorType> {
+        let at = self.address(addr, 1)?;
+        Ok(self.memory[at])
+    }
+
+    pub fn store_byte(&mut self, addr: Cell, value: u8) -> Result<(), ErrorType> {
+        let at = self.address(addr, 1)?;
+        self.memory[at] = value;
+        Ok(())
+    }
+
+    /// Get the instruction at `at` in the definition currently executing.
+    pub fn instruction(&self, at: usize) -> Option<Instruction> {
+        let xt = self.code?;
+        match &self.dictionary[xt].function {
+            Function::UserDefined(code) => code.get(at).copied(),
+            _ => None,
+        }
+    }
+
+    /// Get the length of the definition currently executing.
+    pub fn code_len(&self) -> usize {
+        match self.code.map(|xt| &self.dictionary[xt].function) {
+            Some(Function::UserDefined(code)) => code.len(),
+            _ => 0,
+        }
+    }
+
+    /// Define a word on behalf of the running program, which unlike the
+    /// machine's own definitions counts against the dictionary limit.
+    pub fn define_checked(&mut self, name: &str, function: Function) -> Result<usize, ErrorType> {
+        if self.dictionary.len() >= self.max_dictionary {
+            return Err(ErrorType::DictionaryOverflow);
+        }
+        Ok(self.define(name, function))
+    }
+
+    /// Add a dictionary entry that runs even while compiling.
+    pub fn define_immediate(&mut self, name: &str, function: Function) -> usize {
+        let xt = self.define(name, function);
+        self.dictionary[xt].immediate = true;
+        xt
+    }
+
+    /// Whether the outer interpreter is compiling, as recorded in STATE.
+    pub fn compiling(&self) -> bool {
+        !matches!(self.fetch(self.state), Ok(0) | Err(_))
+    }
+
+    pub fn set_compiling(&mut self, compiling: bool) -> Result<(), ErrorType> {
+        self.store(self.state, if compiling { -1 } else { 0 })
+    }
+
+    /// The radix used to parse and print numbers, as recorded in BASE.
+    /// Anything outside 2 to 36 is treated as decimal.
+    pub fn radix(&self) -> u32 {
+        match self.fetch(self.base) {
+            Ok(n) if (2..=36).contains(&n) => n as u32,
+            _ => 10,
+        }
+    }
+
+    /// Parse a number in the current base into the literals it compiles to.
+    /// A leading `$`, `#` or `%` selects hex, decimal or binary instead, `'c'`
+    /// gives a character's code and a trailing `.` makes a double-cell number.
+    pub fn parse_number(&self, word: &str) -> Option<Vec<Instruction>> {
+        let chars: Vec<char> = word.chars().collect();
+        if chars.len() == 3 && chars[0] == '\'' && chars[2] == '\'' {
+            return Some(vec![Instruction::Literal(chars[1] as Cell)]);
+        }
+
+        #[cfg(feature = "float")]
+        if self.radix() == 10 {
+            if let Some(f) = float::parse_float(word) {
+                return Some(vec![Instruction::FloatLiteral(f)]);
+            }
+        }
+
+        let (double, word) = match word.strip_suffix('.') {
+            Some(rest) => (true, rest),
+            None => (false, word),
+        };
+        let (radix, digits) = match word.as_bytes().first() {
+            Some(b'$') => (16, &word[1..]),
+            Some(b'#') => (10, &word[1..]),
+            Some(b'%') => (2, &word[1..]),
+            _ => (self.radix(), word),
+        };
+        let (negative, digits) = match digits.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, digits),
+        };
+        if digits.is_empty() {
+            return None;
+        }
+
+        // Accept anything that fits, signed or unsigned.
+        let limit = if double { UDCell::MAX } else { UCell::MAX as UDCell };
+        let mut value: UDCell = 0;
+        for c in digits.chars() {
+            value = value.checked_mul(radix as UDCell)?.checked_add(c.to_digit(radix)? as UDCell)?;
+            if value > limit {
+                return None;
+            }
+        }
+
+        if double {
+            let d = value as DCell;
+            let d = if negative { d.wrapping_neg() } else { d };
+            Some(vec![Instruction::Literal(d as Cell), Instruction::Literal((d >> Cell::BITS) as Cell)])
+        } else {
+            let n = value as UCell as Cell;
+            Some(vec![Instruction::Literal(if negative { n.wrapping_neg() } else { n })])
+        }
+    }
+
+    /// Read the name following a defining word.
+    pub fn parse_name(&mut self) -> Result<String, ErrorType> {
+        match self.parse_word() {
+            Some(word) => Ok(word),
+            None => Err(ErrorType::CompilationError),
+        }
+    }
+
+    // The part of the input buffer holding the current line.
+    fn source(&self) -> &[u8] {
+        let start = self.input_buffer as usize;
+        &self.memory[start..start + self.input_len]
+    }
+
+    // Get >IN, treating anything past the end of the line as the end.
+    fn input_position(&self) -> usize {
+        match self.fetch(self.to_in) {
+            Ok(n) if n >= 0 => (n as usize).min(self.input_len),
+            _ => self.input_len,
+        }
+    }
+
+    fn set_input_position(&mut self, n: usize) {
+        let _ = self.store(self.to_in, n as Cell);
+    }
+
+    /// The rest of the current line that has not been parsed yet.
+    pub fn parse_area(&self) -> &[u8] {
+        &self.source()[self.input_position()..]
+    }
+
+    /// Consume `n` bytes of the parse area.
+    pub fn skip_input(&mut self, n: usize) {
+        let position = self.input_position() + n;
+        self.set_input_position(position);
+    }
+
+    /// Parse the next whitespace-delimited word from the input.
+    pub fn parse_word(&mut self) -> Option<String> {
+        let start = self.input_position();
+        let skipped = self.source()[start..].iter().take_while(|c| c.is_ascii_whitespace()).count();
+        self.set_input_position(start + skipped);
+
+        let word = self.parse(|c| c.is_ascii_whitespace()).0;
+        if word.is_empty() {
+            return None;
+        }
+        Some(word)
+    }
+
+    /// Parse input up to the first byte matching `delimiter`, which is
+    /// consumed but not returned. Also returns whether it was found before
+    /// the end of the line.
+    pub fn parse<F: Fn(u8) -> bool>(&mut self, delimiter: F) -> (String, bool) {
+        let start = self.input_position();
+        let rest = &self.source()[start..];
+        let (len, found) = match rest.iter().position(|c| delimiter(*c)) {
+            Some(n) => (n, true),
+            None => (rest.len(), false),
+        };
+
+        let text = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.set_input_position(start + len + found as usize);
+        (text, found)
+    }
+
+    /// Move on to the next pending line of input, if there is one.
+    pub fn refill(&mut self) -> Result<bool, ErrorType> {
+        let line = match self.pending.pop_front() {
+            Some(line) => line,
+            None => return Ok(false),
+        };
+        if line.len() > INPUT_SIZE {
+            self.pending.clear();
+            return Err(ErrorType::InputTooLong);
+        }
+
+        let start = self.input_buffer as usize;
+        self.memory[start..start + line.len()].copy_from_slice(line.as_bytes());
+        self.input_len = line.len();
+        self.set_input_position(0);
+        Ok(true)
+    }
+
+    /// Interpret source text a line at a time. An error discards the rest of
+    /// the source and resets the machine, keeping the data and float stacks.
+    pub fn evaluate(&mut self, source: &str) -> Result<(), ErrorType> {
+        self.pending = source.lines().map(String::from).collect();
+        let result = self.interpret_lines();
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    fn interpret_lines(&mut self) -> Result<(), ErrorType> {
+        while self.refill()? {
+            self.interpret()?;
+        }
+
+        Ok(())
+    }
+
+    /// Go back to interpreting after an error. Pending source, unfinished
+    /// definitions and the return and loop stacks are discarded, but the data
+    /// and float stacks are kept so no values are lost.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.compile_buffer.clear();
+        let _ = self.set_compiling(false);
+        self.frames.clear();
+        self.code = None;
         self.pc = 0;
-        self.data = input.to_vec();
-        while self.pc < input.len() {
-            // Get value.
-            let value = &input[self.pc];
-            self.pc += 1;
+        self.handoff = None;
+        self.return_stack.clear();
+        self.loop_stack.clear();
+    }
 
-            // Convert the current cell to string.
-            let current_word = match value {
-                Value::Number(n) => n.to_string(),
-                Value::Word(w) => w.clone(),
-            };
+    /// Append an instruction to the definition being compiled.
+    pub fn compile_instruction(&mut self, instruction: Instruction) -> Result<(), ErrorType> {
+        if self.compile_buffer.len() >= self.max_definition {
+            return Err(ErrorType::DefinitionTooLong);
+        }
+        self.compile_buffer.push(instruction);
+        Ok(())
+    }
 
+    // Interpret or compile each word on the current line.
+    fn interpret(&mut self) -> Result<(), ErrorType> {
+        while let Some(word) = self.parse_word() {
             // If we're in compile mode, keep compiling.
-            if self.compile_mode && current_word != ";" {
-                if let Err(e) = self.compile_word(&value) {
+            if self.compiling() {
+                if let Err(e) = self.compile_word(&word) {
+                    let _ = self.set_compiling(false);
+                    self.compile_buffer.clear();
                     return Err(e);
                 }
-
                 continue;
             }
 
-            // Get the current word.
-            let word = match value {
-                Value::Number(n) => {
-                    self.push(*n);
-                    continue;
+            match self.names.get(&word) {
+                Some(xt) => self.execute_xt(*xt)?,
+                None => match self.parse_number(&word) {
+                    Some(literals) => {
+                        for literal in literals {
+                            self.step(literal)?;
+                        }
+                    },
+                    None => {
+                        self.print(&format!("{}?\n", word))?;
+                        return Err(ErrorType::WordNotFound);
+                    }
                 },
-                Value::Word(s) => s,
             };
+        }
 
-            match self.dictionary.get(word) {
-                Some(Function::Builtin(f)) => {
-                    if let Err(e) = f(self) {
-                        return Err(e);
+        Ok(())
+    }
+
+    pub fn execute_xt(&mut self, xt: usize) -> Result<(), ErrorType> {
+        let depth = self.frames.len();
+        let rdepth = self.return_stack.len();
+        let result = self.invoke(xt).and_then(|_| self.run(depth));
+
+        // Drop whatever the failed word left behind.
+        if result.is_err() && self.frames.len() > depth {
+            self.frames.truncate(depth + 1);
+            self.return_stack.truncate(rdepth);
+            self.exit();
+        }
+        result
+    }
+
+    /// Check that a cell holds a valid execution token.
+    pub fn execution_token(&self, xt: Cell) -> Result<usize, ErrorType> {
+        if xt < 0 || xt as usize >= self.dictionary.len() {
+            return Err(ErrorType::InvalidAddress);
+        }
+        Ok(xt as usize)
+    }
+
+    /// Perform a word's behavior. Definitions are entered by pushing a new
+    /// frame, so they only run once control returns to the inner interpreter.
+    pub fn invoke(&mut self, mut xt: usize) -> Result<(), ErrorType> {
+        // Deferred words, does> behaviors and execute hand off to another
+        // word. Follow them here rather than recursing, counting each hand
+        // off against the call depth so a cycle can't run forever.
+        let mut handoffs = 0;
+        loop {
+            if self.frames.len() + handoffs >= self.max_call_depth {
+                return Err(ErrorType::ReturnStackOverflow);
+            }
+            handoffs += 1;
+
+            match self.dictionary[xt].function {
+                Function::Builtin(f, arity) => {
+                    self.check_arity(arity)?;
+                    f(self)?;
+                    match self.handoff.take() {
+                        Some(next) => xt = next,
+                        None => return Ok(()),
                     }
                 },
-                Some(Function::UserDefined(f)) => {
-                    let function = f.clone();
-                    self.return_stack.push(self.pc);
-                    self.context.push(Box::new(self.data.clone()));
-
-                    if let Err(e) = self.execute(&function) {
-                        return Err(e);
-                    };
-
-                    self.data = *self.context.pop().unwrap();
-                    self.pc = match self.return_stack.pop() {
-                        Some(n) => n,
-                        None => return Err(ErrorType::StackUnderflow),
-                    };
+                Function::UserDefined(_) => return self.call(xt),
+                Function::Created { body, does } => {
+                    self.push(body as Cell)?;
+                    match does {
+                        Some(does) => xt = does,
+                        None => return Ok(()),
+                    }
                 },
-                Some(Function::Action) => {
-                    return Err(ErrorType::OutsideCompileMode);
+                Function::Value(body) => {
+                    let value = self.fetch(body as Cell)?;
+                    return self.push(value);
                 },
+                Function::Deferred(Some(action)) => xt = action,
+                Function::Deferred(None) => return Err(ErrorType::UninitializedDeferred),
+                Function::Action => return Err(ErrorType::OutsideCompileMode),
+            }
+        }
+    }
+
+    // Enter a definition, saving the caller in a new frame.
+    fn call(&mut self, xt: usize) -> Result<(), ErrorType> {
+        if self.frames.len() >= self.max_call_depth {
+            return Err(ErrorType::ReturnStackOverflow);
+        }
+
+        self.frames.push(Frame { code: self.code, pc: self.pc, loops: self.loop_stack.len() });
+        self.code = Some(xt);
+        self.pc = 0;
+        Ok(())
+    }
+
+    /// Return from the current definition, discarding any loops it left open.
+    pub fn exit(&mut self) {
+        if let Some(frame) = self.frames.pop() {
+            self.code = frame.code;
+            self.pc = frame.pc;
+            self.loop_stack.truncate(frame.loops);
+        }
+    }
+
+    // Run definitions until the frame stack unwinds back to `depth`.
+    fn run(&mut self, depth: usize) -> Result<(), ErrorType> {
+        while self.frames.len() > depth {
+            let instruction = match self.instruction(self.pc) {
+                Some(instruction) => instruction,
                 None => {
-                    println!("{}?", word);
-                    return Err(ErrorType::WordNotFound);
+                    // Fell off the end of the definition, so return.
+                    self.exit();
+                    continue;
                 }
             };
+            self.pc += 1;
+            self.step(instruction)?;
         }
 
-        return Ok(())
+        Ok(())
     }
 
-    fn compile_word(&mut self, value: &Value) -> Result<(), ErrorType> {
-        self.compile_buffer.push(value.clone());
-        return Ok(());
+    // Carry out a single instruction.
+    fn step(&mut self, instruction: Instruction) -> Result<(), ErrorType> {
+        match instruction {
+            Instruction::Call(xt) => self.invoke(xt)?,
+            Instruction::Literal(n) => self.push(n)?,
+            #[cfg(feature = "float")]
+            Instruction::FloatLiteral(f) => self.fpush(f)?,
+        }
+
+        Ok(())
+    }
+
+    fn compile_word(&mut self, word: &str) -> Result<(), ErrorType> {
+        match self.names.get(word) {
+            Some(xt) if self.dictionary[*xt].immediate => self.execute_xt(*xt),
+            Some(xt) => self.compile_instruction(Instruction::Call(*xt)),
+            None => match self.parse_number(word) {
+                Some(literals) => literals.into_iter().try_for_each(|literal| self.compile_instruction(literal)),
+                None => {
+                    self.print(&format!("undefined word: {}\n", word))?;
+                    Err(ErrorType::CompilationError)
+                }
+            },
+        }
     }
 }
 
 fn compile(machine: &mut Machine) -> Result<(), ErrorType> {
-    machine.compile_mode = true;
-    Ok(())
+    // Get the compiled definition name.
+    machine.compile_name = machine.parse_name()?;
+
+    machine.compile_buffer.clear();
+    machine.set_compiling(true)
+}
+
+fn compile_noname(machine: &mut Machine) -> Result<(), ErrorType> {
+    machine.compile_name.clear();
+    machine.compile_buffer.clear();
+    machine.set_compiling(true)
 }
 
 fn finish_compile(machine: &mut Machine) -> Result<(), ErrorType> {
-    if !machine.compile_mode {
+    if !machine.compiling() {
         return Err(ErrorType::OutsideCompileMode);
     }
 
-    machine.compile_mode = false;
+    machine.set_compiling(false)?;
 
-    // Get the compiled definition name.
-    let word;
-    if machine.compile_buffer.len() > 0 {
-        word = match machine.compile_buffer.remove(0) {
-            Value::Number(n) => n.to_string(),
-            Value::Word(w) => w
-        };
-    } else {
-        return Err(ErrorType::CompilationError);
+    // Resolve control structures into runtime words and branch offsets.
+    let mut buffer = std::mem::take(&mut machine.compile_buffer);
+    let does = machine.builtins["does>"];
+    let mut definition;
+    match buffer.iter().position(|i| matches!(i, Instruction::Call(xt) if *xt == does)) {
+        Some(at) => {
+            // The code after does> becomes a nameless word that created
+            // words run, and the defining word hands it over and returns.
+            let behavior = buffer.split_off(at + 1);
+            buffer.pop();
+            let this = machine.dictionary.len();
+            let behavior = resolve_control(machine, &behavior, this)?;
+            definition = resolve_control(machine, &buffer, this + 1)?;
+            let xt = machine.define_checked("", Function::UserDefined(behavior))?;
+            definition.push(Instruction::Call(machine.builtins["(does>)"]));
+            definition.push(Instruction::Literal(xt as Cell));
+        },
+        None => definition = resolve_control(machine, &buffer, machine.dictionary.len())?,
     }
 
-    // Check if words in definition are valid.
-    let mut string_literal = false;
-    for word in machine.compile_buffer.clone() {
-        if let Value::Word(w) = word {
-            if w.ends_with("\"") && string_literal {
-                string_literal = false;
-                continue;
-            }
+    let name = std::mem::take(&mut machine.compile_name);
+    let xt = machine.define_checked(&name, Function::UserDefined(definition))?;
 
-            if string_literal {
-                continue;
-            }
+    // Nameless definitions are only reachable through their token.
+    if name.is_empty() {
+        machine.push(xt as Cell)?;
+    }
+    Ok(())
+}
 
-            if w == ".\"" {
-                string_literal = true;
-            }
+// Each control structure remembers the return stack depth it was opened at.
+enum Control {
+    Do { body: usize, exit: Option<usize>, leaves: Vec<usize>, rdepth: Cell },
+    Begin { start: usize, whiles: Vec<usize>, rdepth: Cell },
+    If { at: usize, rdepth: Cell },
+    Else { at: usize, rdepth: Cell, exited: bool },
+    Case { endofs: Vec<usize>, rdepth: Cell },
+    Of { at: usize, rdepth: Cell },
+}
 
-            if let None = machine.dictionary.get(&w) {
-                println!("undefined word: {}", w);
-                machine.compile_buffer.clear();
-                return Err(ErrorType::CompilationError);
-            }
+impl Control {
+    fn rdepth(&self) -> Cell {
+        match self {
+            Control::Do { rdepth, .. }
+            | Control::Begin { rdepth, .. }
+            | Control::If { rdepth, .. }
+            | Control::Else { rdepth, .. }
+            | Control::Case { rdepth, .. }
+            | Control::Of { rdepth, .. } => *rdepth,
         }
     }
+}
 
-    machine.dictionary.insert(word.clone(), Function::UserDefined(machine.compile_buffer.clone()));
+// Offsets are relative to the offset cell itself, so a branch at cell `from`
+// lands on `from + offset`.
+fn offset(from: usize, to: usize) -> Instruction {
+    Instruction::Literal(to as Cell - from as Cell)
+}
 
-    machine.compile_buffer.clear();
+// Every path through a control structure must leave the return stack at
+// the depth it had when the structure was opened.
+fn check_rdepth(rdepth: Cell, expected: Cell) -> Result<(), ErrorType> {
+    if rdepth != expected {
+        return Err(ErrorType::UnbalancedReturnStack);
+    }
     Ok(())
 }
 
-fn translate_if(values: &mut Vec<Value>) -> Result<(), ErrorType> {
-    let mut i = 0;
-    while i < values.len() {
-        if let Value::Word(w) = &values[i] {
-            if w == "if" {
-                values[i] = Value::Word("0branch".to_string());
-                let offset = match calculate_offset(i, values) {
-                    Ok(n) => n,
-                    Err(e) => return Err(e),
-                };
-                values.insert(i + 1, Value::Number(offset - 1));
-                values.remove(i + offset as usize);
+// `this` is the execution token the definition will be given, for recurse.
+fn resolve_control(machine: &Machine, code: &[Instruction], this: usize) -> Result<Vec<Instruction>, ErrorType> {
+    let mut resolved: Vec<Instruction> = Vec::new();
+    let mut controls: Vec<Control> = Vec::new();
+    let mut rdepth: Cell = 0;
+    // Whether the code since the last control word follows an exit.
+    let mut exited = false;
+    let runtime = |name: &str| Instruction::Call(machine.builtins[name]);
+
+    for instruction in code {
+        let entry = match instruction {
+            Instruction::Call(xt) => &machine.dictionary[*xt],
+            _ => {
+                resolved.push(*instruction);
+                continue;
             }
-        }
-        i += 1;
-    }
+        };
 
-    return Ok(());
-}
+        // Track cells the definition keeps on the return stack.
+        if let Function::Builtin(..) = entry.function {
+            let (needed, change) = match entry.name.as_str() {
+                ">r" => (0, 1),
+                "2>r" => (0, 2),
+                "r>" => (1, -1),
+                "2r>" => (2, -2),
+                "r@" => (1, 0),
+                "2r@" => (2, 0),
+                "exit" => {
+                    check_rdepth(rdepth, 0)?;
+                    // Nothing after exit runs, so the rest of the branch
+                    // leaves whatever depth the enclosing structure expects.
+                    rdepth = controls.last().map_or(0, Control::rdepth);
+                    exited = true;
+                    (0, 0)
+                },
+                _ => (0, 0),
+            };
+            if rdepth < needed {
+                return Err(ErrorType::UnbalancedReturnStack);
+            }
+            rdepth += change;
+        }
 
-fn calculate_offset(i: usize, values: &mut Vec<Value>) -> Result<i32, ErrorType> {
-    let mut offset: i32 = 1;
-    let mut ifs = 0;
-    let iter = values.iter().skip(i);
-    for v in iter {
-        if let Value::Word(w) = &v {
-            if w == "then" && ifs == 0 {
-                return Ok(offset);
-            } else if w == "if" {
-                ifs += 1;
-                offset += 1;
-            } else if w == "then" && ifs != 0 {
-                ifs -= 1;
-                offset -= 1;
+        // Control words are compiled as calls to their marker entries.
+        let w = match entry.function {
+            Function::Action => entry.name.as_str(),
+            _ => {
+                resolved.push(*instruction);
+                continue;
             }
-            offset += 1;
-        } else {
-            offset += 1;
+        };
+
+        let after_exit = std::mem::replace(&mut exited, false);
+        match w {
+            "recurse" => resolved.push(Instruction::Call(this)),
+            "do" => {
+                resolved.push(runtime("(do)"));
+                controls.push(Control::Do { body: resolved.len(), exit: None, leaves: Vec::new(), rdepth });
+            },
+            "?do" => {
+                resolved.push(runtime("(?do)"));
+                resolved.push(Instruction::Literal(0));
+                let exit = Some(resolved.len() - 1);
+                controls.push(Control::Do { body: resolved.len(), exit, leaves: Vec::new(), rdepth });
+            },
+            "leave" => {
+                resolved.push(runtime("(leave)"));
+                resolved.push(Instruction::Literal(0));
+                let at = resolved.len() - 1;
+                match controls.iter_mut().rev().find(|c| matches!(c, Control::Do { .. })) {
+                    Some(Control::Do { leaves, rdepth: expected, .. }) => {
+                        check_rdepth(rdepth, *expected)?;
+                        leaves.push(at);
+                    },
+                    _ => return Err(ErrorType::UnbalancedControl),
+                }
+            },
+            "loop" | "+loop" => {
+                let (body, exit, leaves) = match controls.pop() {
+                    Some(Control::Do { body, exit, leaves, rdepth: expected }) => {
+                        check_rdepth(rdepth, expected)?;
+                        (body, exit, leaves)
+                    },
+                    _ => return Err(ErrorType::UnbalancedControl),
+                };
+                resolved.push(runtime(if w == "loop" { "(loop)" } else { "(+loop)" }));
+                resolved.push(offset(resolved.len(), body));
+
+                let end = resolved.len();
+                for at in exit.into_iter().chain(leaves) {
+                    resolved[at] = offset(at, end);
+                }
+            },
+            "if" => {
+                resolved.push(runtime("0branch"));
+                resolved.push(Instruction::Literal(0));
+                controls.push(Control::If { at: resolved.len() - 1, rdepth });
+            },
+            "else" => {
+                let (orig, expected) = match controls.pop() {
+                    Some(Control::If { at, rdepth }) => (at, rdepth),
+                    _ => return Err(ErrorType::UnbalancedControl),
+                };
+                resolved.push(runtime("branch"));
+                resolved.push(Instruction::Literal(0));
+                controls.push(Control::Else { at: resolved.len() - 1, rdepth, exited: after_exit });
+                resolved[orig] = offset(orig, resolved.len());
+
+                // The false branch starts from the depth at the if.
+                rdepth = expected;
+            },
+            "then" => {
+                let orig = match controls.pop() {
+                    // A true branch that exits doesn't reach then.
+                    Some(Control::Else { at, exited: true, .. }) => at,
+                    Some(Control::If { at, rdepth: expected }) | Some(Control::Else { at, rdepth: expected, .. }) => {
+                        check_rdepth(rdepth, expected)?;
+                        at
+                    },
+                    _ => return Err(ErrorType::UnbalancedControl),
+                };
+                resolved[orig] = offset(orig, resolved.len());
+            },
+            "case" => {
+                controls.push(Control::Case { endofs: Vec::new(), rdepth });
+            },
+            "of" => {
+                if !matches!(controls.last(), Some(Control::Case { .. })) {
+                    return Err(ErrorType::UnbalancedControl);
+                }
+                resolved.push(runtime("(of)"));
+                resolved.push(Instruction::Literal(0));
+                controls.push(Control::Of { at: resolved.len() - 1, rdepth });
+            },
+            "endof" => {
+                let orig = match controls.pop() {
+                    Some(Control::Of { at, rdepth: expected }) => {
+                        check_rdepth(rdepth, expected)?;
+                        at
+                    },
+                    _ => return Err(ErrorType::UnbalancedControl),
+                };
+                resolved.push(runtime("branch"));
+                resolved.push(Instruction::Literal(0));
+                let at = resolved.len() - 1;
+                if let Some(Control::Case { endofs, .. }) = controls.last_mut() {
+                    endofs.push(at);
+                }
+                resolved[orig] = offset(orig, resolved.len());
+            },
+            "endcase" => {
+                let endofs = match controls.pop() {
+                    Some(Control::Case { endofs, rdepth: expected }) => {
+                        check_rdepth(rdepth, expected)?;
+                        endofs
+                    },
+                    _ => return Err(ErrorType::UnbalancedControl),
+                };
+
+                // Only the default path still has the selector to drop.
+                resolved.push(runtime("drop"));
+                let end = resolved.len();
+                for at in endofs {
+                    resolved[at] = offset(at, end);
+                }
+            },
+            "begin" => {
+                controls.push(Control::Begin { start: resolved.len(), whiles: Vec::new(), rdepth });
+            },
+            "while" => {
+                resolved.push(runtime("0branch"));
+                resolved.push(Instruction::Literal(0));
+                let at = resolved.len() - 1;
+                match controls.last_mut() {
+                    Some(Control::Begin { whiles, rdepth: expected, .. }) => {
+                        check_rdepth(rdepth, *expected)?;
+                        whiles.push(at);
+                    },
+                    _ => return Err(ErrorType::UnbalancedControl),
+                }
+            },
+            "until" | "again" | "repeat" => {
+                let (start, whiles) = match controls.pop() {
+                    Some(Control::Begin { start, whiles, rdepth: expected }) => {
+                        check_rdepth(rdepth, expected)?;
+                        (start, whiles)
+                    },
+                    _ => return Err(ErrorType::UnbalancedControl),
+                };
+                if w == "repeat" && whiles.is_empty() {
+                    return Err(ErrorType::UnbalancedControl);
+                }
+
+                resolved.push(runtime(if w == "until" { "0branch" } else { "branch" }));
+                resolved.push(offset(resolved.len(), start));
+
+                let end = resolved.len();
+                for at in whiles {
+                    resolved[at] = offset(at, end);
+                }
+            },
+            _ => resolved.push(*instruction),
         }
     }
 
-    return Err(ErrorType::UnbalancedControl);
+    if !controls.is_empty() {
+        return Err(ErrorType::UnbalancedControl);
+    }
+
+    check_rdepth(rdepth, 0)?;
+
+    Ok(resolved)
 }
@@ -1,18 +1,83 @@
+#[cfg(feature = "float")]
+pub mod float;
 pub mod instructions;
 pub mod machine;
+#[cfg(test)]
+mod tests;
 
-#[derive(Debug, Clone)]
-pub enum Value {
-    Word(String),
-    Number(i32)
+/// A cell, the machine's basic unit of data, along with its unsigned form
+/// and the double-cell types twice its width. If both cell16 and cell64 are
+/// enabled, cell64 wins.
+#[cfg(all(feature = "cell16", not(feature = "cell64")))]
+pub type Cell = i16;
+#[cfg(all(feature = "cell16", not(feature = "cell64")))]
+pub type UCell = u16;
+#[cfg(all(feature = "cell16", not(feature = "cell64")))]
+pub type DCell = i32;
+#[cfg(all(feature = "cell16", not(feature = "cell64")))]
+pub type UDCell = u32;
+
+#[cfg(feature = "cell64")]
+pub type Cell = i64;
+#[cfg(feature = "cell64")]
+pub type UCell = u64;
+#[cfg(feature = "cell64")]
+pub type DCell = i128;
+#[cfg(feature = "cell64")]
+pub type UDCell = u128;
+
+#[cfg(not(any(feature = "cell16", feature = "cell64")))]
+pub type Cell = i32;
+#[cfg(not(any(feature = "cell16", feature = "cell64")))]
+pub type UCell = u32;
+#[cfg(not(any(feature = "cell16", feature = "cell64")))]
+pub type DCell = i64;
+#[cfg(not(any(feature = "cell16", feature = "cell64")))]
+pub type UDCell = u64;
+
+/// Size in bytes of a cell in data space.
+pub const CELL_SIZE: usize = std::mem::size_of::<Cell>();
+
+/// Size in bytes of a floating-point number in data space.
+#[cfg(feature = "float")]
+pub const FLOAT_SIZE: usize = std::mem::size_of::<f64>();
+
+/// Size in bytes of the buffer holding the line being interpreted.
+pub const INPUT_SIZE: usize = 1024;
+
+/// Size in bytes of the buffer that pictured numeric output is built in.
+pub const HOLD_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Instruction {
+    Call(usize),
+    Literal(Cell),
+    #[cfg(feature = "float")]
+    FloatLiteral(f64),
 }
 
 pub enum ErrorType {
     BranchOutOfBounds,
     CompilationError,
+    DataSpaceOverflow,
+    DefinitionTooLong,
+    DictionaryOverflow,
+    DivisionByZero,
+    #[cfg(feature = "float")]
+    FloatStackOverflow,
+    #[cfg(feature = "float")]
+    FloatStackUnderflow,
+    InvalidAddress,
+    InputTooLong,
     InvalidOffset,
+    IoError,
     OutsideCompileMode,
+    PicturedOutputOverflow,
+    ReturnStackOverflow,
+    StackOverflow,
     StackUnderflow,
     UnbalancedControl,
+    UnbalancedReturnStack,
+    UninitializedDeferred,
     WordNotFound,
 }
@@ -0,0 +1,357 @@
+use std::cell::RefCell;
+use std::io;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+use crate::vm::machine::Machine;
+use crate::vm::ErrorType;
+
+// A writer that keeps what the machine prints where the test can read it.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn machine() -> (Machine, Output) {
+    let output = Output::default();
+    let machine = Machine::with_streams(io::Cursor::new(Vec::new()), output.clone());
+    (machine, output)
+}
+
+// Evaluate source that should succeed and return what it printed.
+fn run(source: &str) -> String {
+    let (mut machine, output) = machine();
+    assert!(machine.evaluate(source).is_ok(), "failed: {}", source);
+    let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+    text
+}
+
+// Evaluate source that should fail and return the error.
+fn fail(source: &str) -> ErrorType {
+    let (mut machine, _) = machine();
+    match machine.evaluate(source) {
+        Ok(_) => panic!("succeeded: {}", source),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn output_goes_to_the_stream() {
+    assert_eq!(run("65 emit 66 emit cr 1 . space 2 . 3 spaces s\" x\" type"), "AB\n1  2    x");
+    assert_eq!(run("1 2 .s"), "<2> 1 2 ");
+}
+
+#[test]
+fn input_comes_from_the_stream() {
+    let output = Output::default();
+    let input = io::Cursor::new(b"xyz\nhello world\n".to_vec());
+    let mut machine = Machine::with_streams(input, output.clone());
+    let source = "key emit key emit key emit key . create b 5 allot b 5 accept b swap type key .";
+    assert!(machine.evaluate(source).is_ok());
+    assert_eq!(String::from_utf8(output.0.borrow().clone()).unwrap(), "xyz10 hello-1 ");
+}
+
+#[test]
+fn if_else_then() {
+    assert_eq!(run(": t if 1 else 2 then . ; -1 t 0 t"), "1 2 ");
+    assert_eq!(run(": t if 1 . then 3 . ; 0 t 5 t"), "3 1 3 ");
+    assert_eq!(run(": t if if 1 else 2 then else 3 then . ; -1 -1 t 0 -1 t 0 t"), "1 2 3 ");
+}
+
+#[test]
+fn unbalanced_control() {
+    assert!(matches!(fail(": t if ;"), ErrorType::UnbalancedControl));
+    assert!(matches!(fail(": t then ;"), ErrorType::UnbalancedControl));
+    assert!(matches!(fail(": t begin if until ;"), ErrorType::UnbalancedControl));
+    assert!(matches!(fail(": t 0 do ;"), ErrorType::UnbalancedControl));
+}
+
+#[test]
+fn control_ignores_redefined_runtime_words() {
+    let source = ": 0branch ; : branch ; : (do) ; : (loop) ; : (leave) ;";
+    let test = ": t if 1 else 2 then . 3 0 do i 1 = if leave then i . loop ; -1 t 0 t";
+    assert_eq!(run(&format!("{} {}", source, test)), "1 0 2 0 ");
+}
+
+#[test]
+fn case_of() {
+    let source = ": t case 1 of 10 endof 2 of 20 endof 99 swap endcase . ;";
+    assert_eq!(run(&format!("{} 1 t 2 t 3 t", source)), "10 20 99 ");
+    assert_eq!(run(&format!(": drop 0 . ; {} 1 t 3 t", source)), "10 99 ");
+}
+
+#[test]
+fn unbalanced_return_stack() {
+    assert!(matches!(fail(": t >r ;"), ErrorType::UnbalancedReturnStack));
+    assert!(matches!(fail(": t 1 >r exit ;"), ErrorType::UnbalancedReturnStack));
+    assert!(matches!(fail(": t if >r then ;"), ErrorType::UnbalancedReturnStack));
+    assert_eq!(run(": t >r 1 r> + ; 2 t ."), "3 ");
+    let source = ": t dup >r 0= if r> drop exit then r> . ;";
+    assert_eq!(run(&format!("{} 0 t 5 t", source)), "5 ");
+    assert_eq!(run(": t >r if r> exit else r> drop then ; -1 1 t . 0 2 t"), "1 ");
+    assert!(matches!(fail(": t >r if r> exit then ;"), ErrorType::UnbalancedReturnStack));
+}
+
+#[cfg(feature = "cell64")]
+#[test]
+fn wide_cells() {
+    assert_eq!(run("4294967296 . 2147483647 1 + ."), "4294967296 2147483648 ");
+}
+
+#[cfg(all(feature = "cell16", not(feature = "cell64")))]
+#[test]
+fn narrow_cells() {
+    assert_eq!(run("32767 1 + . 65535 . 300 300 * ."), "-32768 -1 24464 ");
+    assert!(matches!(fail("70000"), ErrorType::WordNotFound));
+}
+
+#[test]
+fn underflow_leaves_stack_untouched() {
+    for (word, before) in [("+", vec![5]), ("2swap", vec![1, 2, 3]), ("within", vec![1, 2])] {
+        let (mut machine, _) = machine();
+        machine.stack = before.clone();
+        assert!(matches!(machine.evaluate(word), Err(ErrorType::StackUnderflow)));
+        assert_eq!(machine.stack, before, "{}", word);
+    }
+}
+
+#[test]
+fn errors_reset_but_keep_data() {
+    let (mut machine, _) = machine();
+    assert!(machine.evaluate("1 2 3 >r : t 4 5 nothing ;").is_err());
+    assert_eq!(machine.stack, vec![1, 2]);
+    assert!(machine.return_stack.is_empty());
+    assert!(!machine.compiling());
+    assert!(machine.evaluate("+ +").is_err());
+    assert_eq!(machine.stack, vec![3]);
+    #[cfg(feature = "float")]
+    {
+        assert!(machine.evaluate("1.5e0 f+").is_err());
+        assert_eq!(machine.float_stack, vec![1.5]);
+    }
+}
+
+#[test]
+fn deferred_words() {
+    assert_eq!(run("defer d ' dup is d 3 d . . action-of d ' dup = ."), "3 3 -1 ");
+    assert_eq!(run("defer d : t ['] + is d ; t 2 3 d ."), "5 ");
+    assert_eq!(run(":noname 5 . ; execute ' 1+ 6 swap execute ."), "5 7 ");
+    assert!(matches!(fail("defer d d"), ErrorType::UninitializedDeferred));
+    let source = ": defer! 2drop ; : defer@ drop 0 ; defer d";
+    let test = ": t ['] 1+ is d action-of d ; t 4 d . ' 1+ = .";
+    assert_eq!(run(&format!("{} {}", source, test)), "5 -1 ");
+}
+
+#[test]
+fn self_deferred_word_overflows() {
+    assert!(matches!(fail("defer a ' a is a a"), ErrorType::ReturnStackOverflow));
+}
+
+#[test]
+fn deferred_cycle_overflows() {
+    let source = "defer a defer b ' b is a ' a is b a";
+    assert!(matches!(fail(source), ErrorType::ReturnStackOverflow));
+}
+
+#[test]
+fn execute_chain_overflows() {
+    let source = ": t 10000 0 do ['] execute loop ; t execute";
+    assert!(matches!(fail(source), ErrorType::ReturnStackOverflow));
+}
+
+#[test]
+fn comments_and_input() {
+    assert_eq!(run("1 ( 2 . ) . \\ 3 .\n4 ."), "1 4 ");
+    assert_eq!(run(": t ( n -- ) . ; 5 t"), "5 ");
+    assert_eq!(run("source nip >in ! 1 .\n2 ."), "2 ");
+    assert_eq!(run(">in @ ."), "6 ");
+}
+
+#[test]
+fn allot_keeps_reserved_space() {
+    assert!(matches!(fail("here negate allot"), ErrorType::InvalidAddress));
+    assert_eq!(run("here 8 allot -8 allot here = . 1 2 + ."), "-1 3 ");
+}
+
+#[test]
+fn definition_limit() {
+    let source = ": m begin ['] dup compile, again ; immediate : t m ;";
+    assert!(matches!(fail(source), ErrorType::DefinitionTooLong));
+    let (machine, _) = machine();
+    let mut machine = machine.definition_limit(4);
+    assert!(machine.evaluate(": t 1 2 3 4 ; t").is_ok());
+    assert!(matches!(machine.evaluate(": u 1 2 3 4 5 ;"), Err(ErrorType::DefinitionTooLong)));
+    assert!(matches!(machine.evaluate(": u 1 2 3 4 [ 5 ] literal ;"), Err(ErrorType::DefinitionTooLong)));
+    assert_eq!(machine.stack, vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn defining_words() {
+    assert_eq!(run("create x 3 , x @ . 5 constant five five ."), "3 5 ");
+    assert_eq!(run(": k create , does> @ ; 7 k seven seven ."), "7 ");
+    assert_eq!(run("1 value v v . 2 to v v . : t 3 to v ; t v ."), "1 2 3 ");
+    assert_eq!(run("variable x 4 x ! x @ . 1 2 2constant p p . ."), "4 2 1 ");
+}
+
+#[test]
+fn defining_words_ignore_redefined_words() {
+    let source = ": ! 2drop ; : @ drop 0 ; : 2@ drop 0 0 ;";
+    let test = "1 value v : t 2 to v ; t v . 3 constant c c . 4 5 2constant p p . .";
+    assert_eq!(run(&format!("{} {}", source, test)), "2 3 5 4 ");
+    assert_eq!(run(": (does>) ; : k create , does> @ ; 6 k x x ."), "6 ");
+}
+
+#[test]
+fn string_literals() {
+    assert_eq!(run(".\" hi there\" : t .\" in\" ; t"), "hi therein");
+    assert_eq!(run("s\" abc\" type : t s\" de\" type ; t"), "abcde");
+    assert_eq!(run("c\" abc\" count . drop : t c\" xy\" count type ; t"), "3 xy");
+    assert_eq!(run("s\\\" a\\tb\\q\\x41\\n\" type"), "a\tb\"A\n");
+    assert_eq!(run("s\\\" \\m\\z\" dup . type"), "3 \r\n\0");
+    assert_eq!(run(": type 2drop ; : t .\" shown\" ; t"), "shown");
+}
+
+#[test]
+fn immediate_and_postpone() {
+    assert_eq!(run(": i 1 . ; immediate : t i 2 . ; 3 . t"), "1 3 2 ");
+    assert_eq!(run(": t [ 2 3 + ] literal . ; t"), "5 ");
+    assert_eq!(run(": my-if postpone if ; immediate : t my-if 1 . then ; -1 t 0 t"), "1 ");
+    assert_eq!(run(": d postpone dup ; immediate : t d * ; 3 t ."), "9 ");
+    assert_eq!(run(": compile, drop ; : d postpone dup ; immediate : t d + ; 4 t ."), "8 ");
+    assert!(matches!(fail("1 literal"), ErrorType::OutsideCompileMode));
+}
+
+#[test]
+fn key_question_does_not_wait() {
+    let output = Output::default();
+    let mut machine = Machine::with_streams(io::Cursor::new(b"ab".to_vec()), output.clone());
+    assert!(machine.evaluate("key? . key . key . key? .").is_ok());
+    assert_eq!(String::from_utf8(output.0.borrow().clone()).unwrap(), "-1 97 98 0 ");
+
+    let input = io::BufReader::new(io::Cursor::new(b"c".to_vec()));
+    let mut machine = Machine::with_streams(input, Output::default());
+    assert!(machine.evaluate("key? 0= key 99 = and").is_ok());
+    assert_eq!(machine.stack, vec![-1]);
+}
+
+#[test]
+fn counted_loops() {
+    assert_eq!(run(": t 4 0 do i . loop ; t"), "0 1 2 3 ");
+    assert_eq!(run(": t 0 10 do i . -3 +loop ; t"), "10 7 4 1 ");
+    assert_eq!(run(": t 0 0 ?do i . loop 9 . ; t"), "9 ");
+    assert_eq!(run(": t 2 0 do 2 0 do j i + . loop loop ; t"), "0 1 1 2 ");
+    assert_eq!(run(": t 10 0 do i 3 = if leave then i . loop 9 . ; t"), "0 1 2 9 ");
+    assert_eq!(run(": t 3 0 do i 1 = if unloop exit then i . loop ; t 5 ."), "0 5 ");
+    assert_eq!(run(": t -1 2 do i . -1 +loop ; t"), "2 1 0 -1 ");
+}
+
+#[test]
+fn indefinite_loops() {
+    assert_eq!(run(": t 0 begin dup . 1+ dup 3 = until drop ; t"), "0 1 2 ");
+    assert_eq!(run(": t 0 begin dup 3 < while dup . 1+ repeat drop ; t"), "0 1 2 ");
+    assert_eq!(run(": t 0 begin 1+ dup 3 = if exit then again ; t ."), "3 ");
+}
+
+#[test]
+fn exit_and_recurse() {
+    assert_eq!(run(": t 1 . exit 2 . ; t"), "1 ");
+    assert_eq!(run(": t 5 0 do i 2 = if unloop exit then i . loop ; t 7 ."), "0 1 7 ");
+    assert_eq!(run(": fact dup 1 > if dup 1- recurse * then ; 5 fact ."), "120 ");
+}
+
+#[test]
+fn number_prefixes() {
+    assert_eq!(run("$ff . #10 . %1010 . 'a' . $-10 ."), "255 10 10 97 -16 ");
+    assert_eq!(run("hex ff . 10 . decimal 10 ."), "FF 10 10 ");
+    assert_eq!(run("hex #10 . decimal"), "A ");
+    assert_eq!(run("2 base ! 101 . decimal"), "101 ");
+    assert_eq!(run("123456. d. -5. d."), "123456 -5 ");
+    assert!(matches!(fail("2 base ! 12"), ErrorType::WordNotFound));
+}
+
+#[test]
+fn division_by_zero() {
+    assert!(matches!(fail("1 0 /"), ErrorType::DivisionByZero));
+    assert!(matches!(fail("1 0 mod"), ErrorType::DivisionByZero));
+    assert!(matches!(fail("1 0 /mod"), ErrorType::DivisionByZero));
+    assert!(matches!(fail("1 2 0 */"), ErrorType::DivisionByZero));
+    assert!(matches!(fail("1 0 0 um/mod"), ErrorType::DivisionByZero));
+    assert_eq!(run("7 2 / . 7 2 mod . -7 2 / ."), "3 1 -3 ");
+}
+
+#[test]
+fn division_rounding_and_overflow() {
+    assert_eq!(run("-7 2 /mod . . -7 s>d 2 fm/mod . . -7 s>d 2 sm/rem . ."), "-3 -1 -4 1 -3 -1 ");
+    // The most negative cell divided by -1 wraps around to itself.
+    assert_eq!(run("0 invert 1 rshift invert dup -1 / = ."), "-1 ");
+    let (machine, output) = machine();
+    let mut machine = machine.floored_division(true);
+    assert!(machine.evaluate("-7 2 / . -7 2 mod .").is_ok());
+    assert_eq!(String::from_utf8(output.0.borrow().clone()).unwrap(), "-4 1 ");
+}
+
+#[test]
+fn pictured_output() {
+    assert_eq!(run("-42 5 .r 42 2 .r 123 2 .r 7 3 u.r 5 u. -7 s>d d."), "  -4242123  75 -7 ");
+    assert_eq!(run(": t 0 <# # # [char] . hold #s #> type ; 1234 t"), "12.34");
+    assert_eq!(run("-5 dup abs 0 <# #s rot sign #> type"), "-5");
+    assert_eq!(run("255 hex 0 <# #s #> type decimal"), "FF");
+    let source = ": t 0 0 <# 300 0 do [char] x hold loop ; t";
+    assert!(matches!(fail(source), ErrorType::PicturedOutputOverflow));
+}
+
+#[cfg(feature = "float")]
+#[test]
+fn floats() {
+    assert_eq!(run("1.5e0 2e0 f* f. 1e 4e f/ f. -2.5E1 f."), "3 0.25 -25 ");
+    assert_eq!(run("7 s>f 2e0 f/ f>s . 2e0 fsqrt fdup f* f. 1 2 + ."), "3 2.0000000000000004 3 ");
+    assert_eq!(run("1e0 2e0 f< . 2e0 1e0 fswap f- f. 0e0 f0= ."), "-1 -1 -1 ");
+    assert_eq!(run("fvariable x 2.5e0 x f! x f@ f. 3e0 fconstant three three f."), "2.5 3 ");
+    assert_eq!(run(": t 1.5e0 f+ ; 1e0 t f."), "2.5 ");
+    assert!(matches!(fail("f+"), ErrorType::FloatStackUnderflow));
+}
+
+#[test]
+fn call_depth_limit() {
+    assert!(matches!(fail(": r recurse ; r"), ErrorType::ReturnStackOverflow));
+    assert_eq!(run(": count dup if 1- recurse 1+ then ; 4000 count ."), "4000 ");
+    let (machine, _) = machine();
+    let mut machine = machine.call_depth_limit(10);
+    assert!(machine.evaluate(": count dup if 1- recurse 1+ then ; 8 count").is_ok());
+    assert!(matches!(machine.evaluate("20 count"), Err(ErrorType::ReturnStackOverflow)));
+    assert!(machine.frames.is_empty());
+}
+
+#[test]
+fn resource_limits() {
+    let mut limited = machine().0.stack_limit(3);
+    assert!(matches!(limited.evaluate("1 2 3 4"), Err(ErrorType::StackOverflow)));
+    assert_eq!(limited.stack, vec![1, 2, 3]);
+    #[cfg(feature = "float")]
+    assert!(matches!(limited.evaluate("1e 2e 3e 4e"), Err(ErrorType::FloatStackOverflow)));
+
+    let mut limited = machine().0.return_stack_limit(2);
+    let source = ": t 1 >r 2 >r 3 >r r> r> r> drop drop drop ; t";
+    assert!(matches!(limited.evaluate(source), Err(ErrorType::ReturnStackOverflow)));
+
+    let here = machine().0.here();
+    let mut limited = machine().0.data_space_limit(here + 8);
+    assert!(limited.evaluate("8 allot").is_ok());
+    assert!(matches!(limited.evaluate("1 allot"), Err(ErrorType::DataSpaceOverflow)));
+    assert!(matches!(limited.evaluate("1 ,"), Err(ErrorType::DataSpaceOverflow)));
+
+    let words = machine().0.dictionary.len();
+    let mut limited = machine().0.dictionary_limit(words + 1);
+    assert!(limited.evaluate(": a ;").is_ok());
+    assert!(matches!(limited.evaluate(": b ;"), Err(ErrorType::DictionaryOverflow)));
+    assert!(matches!(limited.evaluate("variable c"), Err(ErrorType::DictionaryOverflow)));
+}